@@ -0,0 +1,4 @@
+#!/bin/bash
+# Runs when LocalStack is ready, creates the queues the READER polls
+awslocal sqs create-queue --queue-name memo-events
+awslocal sqs create-queue --queue-name memo-events-failure
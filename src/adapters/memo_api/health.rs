@@ -0,0 +1,38 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Json},
+    routing::get,
+    Router,
+};
+use serde_json::json;
+use std::sync::Arc;
+
+use crate::services::health::HealthService;
+
+// Probe endpoints, served next to the API by the SERVER and on the admin port otherwise.
+// They are not request logged, probes would drown out the API traffic
+pub fn routes(health_service: Arc<HealthService>) -> Router {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(health_service)
+}
+
+// Liveness only says the process answers, dependencies being down must not get it restarted
+async fn healthz() -> impl IntoResponse {
+    Json(json!({ "status": "ok" }))
+}
+
+async fn readyz(State(health_service): State<Arc<HealthService>>) -> impl IntoResponse {
+    let checks = health_service.readiness().await;
+    let ready = checks.iter().all(|check| check.ok);
+    for check in checks.iter().filter(|check| !check.ok) {
+        tracing::warn!("readiness check {} failed: {}", check.name, check.detail);
+    }
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(json!({
+        "status": if ready { "ready" } else { "not ready" },
+        "checks": checks,
+    })))
+}
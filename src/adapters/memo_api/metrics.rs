@@ -0,0 +1,35 @@
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use metrics_exporter_prometheus::PrometheusHandle;
+use std::time::Instant;
+
+use crate::services::metrics;
+
+// Prometheus scrape endpoint, next to the probes on the API and the admin port
+pub fn routes(handle: PrometheusHandle) -> Router {
+    Router::new()
+        .route("/metrics", get(render_metrics))
+        .with_state(handle)
+}
+
+async fn render_metrics(State(handle): State<PrometheusHandle>) -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], handle.render())
+}
+
+// Labels requests with the route pattern, not the path, so user ids do not become series
+pub async fn record_http_metrics(req: Request, next: Next) -> Response {
+    let route = req.extensions().get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = req.method().to_string();
+    let started = Instant::now();
+    let res = next.run(req).await;
+    metrics::observe_http(method, route, res.status().as_u16(), started.elapsed());
+    res
+}
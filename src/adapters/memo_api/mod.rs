@@ -1 +1,4 @@
-pub mod router;
\ No newline at end of file
+pub mod router;
+pub mod health;
+pub mod metrics;
+pub mod telemetry;
\ No newline at end of file
@@ -1,8 +1,8 @@
 use axum::{
     async_trait,
-    http::{StatusCode, self},
-    extract::{Path, State, Request, FromRequestParts},
-    response::{IntoResponse, Response, Json},
+    http::{StatusCode, HeaderMap, HeaderValue, header, self},
+    extract::{Path, Query, State, Request, FromRequestParts, ws::{Message, WebSocket, WebSocketUpgrade}},
+    response::{IntoResponse, Response, Json, sse::{Event, KeepAlive, Sse}},
     routing::{get, post},
     body::{Body, Bytes},
     middleware::{self, Next},
@@ -15,22 +15,28 @@ use axum_extra::{
 };
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value, to_value};
-use std::sync::Arc;
+use std::{collections::{HashSet, VecDeque}, convert::Infallible, sync::Arc, time::Duration};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_stream::{Stream, StreamExt, StreamMap, wrappers::BroadcastStream};
 use jsonwebtoken::{decode, DecodingKey, Validation};
 use http_body_util::BodyExt;
-use once_cell::sync::Lazy;
+use once_cell::sync::OnceCell;
+use metrics_exporter_prometheus::PrometheusHandle;
+use chrono::{DateTime, FixedOffset};
 
 use crate::{
-    services::notification::{NotificationService, NotificationServiceInterface},
-    adapters::memo_events::processors::model::NotificationStatus
+    services::{notification::SharedNotificationService, health::HealthService, hub::{NotificationHub, HubEvent, HubSubscription}, keys::{EntityKey, UserKey, NotificationKey}, bus::NotificationEvent},
+    adapters::{memo_api::{health, metrics, telemetry}, memo_events::processors::{model::NotificationStatus, event_type_processor::ApplicationError}},
 };
 
 
-static KEYS: Lazy<Keys> = Lazy::new(|| {
-    let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-    tracing::info!("JWT_SECRET: {:?}", secret);
-    Keys::new(secret.as_bytes())
-});
+// idle interval before SSE and WebSocket connections send a keep-alive
+const HEARTBEAT_SECONDS: u64 = 15;
+// message ids a socket remembers, so a fanned-out message is announced on its topic once
+const SOCKET_RECENT_MESSAGES: usize = 100;
+
+// set once by `construct` from the configured JWT_SECRET
+static KEYS: OnceCell<Keys> = OnceCell::new();
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Claims {
@@ -51,6 +57,10 @@ enum AuthError {
 enum ApiServerError {
     InternalServerError(String),
     UnexpectedError(String),
+    Conflict(String),
+    NotFound(String),
+    PreconditionFailed(String),
+    TooManyRequests(String),
 }
 
 struct Keys {
@@ -66,16 +76,47 @@ impl Keys {
 }
 
 pub struct AppService {
-    pub notification_service: NotificationService,
+    pub notification_service: SharedNotificationService,
+    pub notification_hub: Arc<NotificationHub>,
+    pub health_service: Arc<HealthService>,
+    pub metrics_handle: PrometheusHandle,
 }
 
 // Start defining routes
-pub fn construct(app_state: Arc<AppService>) -> Router {
+pub fn construct(app_state: Arc<AppService>, jwt_secret: &str) -> Router {
+    let _ = KEYS.set(Keys::new(jwt_secret.as_bytes()));
+    let health_service = app_state.health_service.clone();
+    let metrics_handle = app_state.metrics_handle.clone();
     Router::new()
         .route("/n/notification/message/:user_id", get(get_notification))
+        .route("/n/notification/message/:user_id/unread-count", get(get_unread_count))
+        .route("/n/notification/message/:user_id/stream", get(stream_notifications))
+        .route("/n/notification/message/:user_id/ws", get(notification_socket))
+        .route("/n/notification/message/:user_id/bulk", post(bulk_update_notification_status))
         .route("/n/notification/message/:user_id/:noti_id", post(update_notification_status))
         .with_state(app_state)
+        .route_layer(middleware::from_fn(metrics::record_http_metrics))
+        .route_layer(middleware::from_fn(telemetry::trace_http_request))
         .layer(middleware::from_fn(print_request_response))
+        .merge(health::routes(health_service))
+        .merge(metrics::routes(metrics_handle))
+}
+
+// Path ids become typed keys, ids that cannot be encoded are rejected as bad input
+fn parse_key<K: EntityKey>(id: String) -> Result<K, ApiServerError> {
+    K::new(id).map_err(|e| ApiServerError::UnexpectedError(e.to_string()))
+}
+
+// The item version is the ETag, clients send it back in If-Match to update only what they last saw
+fn etag(version: i64) -> String {
+    format!("\"{}\"", version)
+}
+
+fn parse_etag(value: &HeaderValue) -> Result<i64, ApiServerError> {
+    value.to_str().ok()
+        .map(|value| value.trim().trim_start_matches("W/").trim_matches('"'))
+        .and_then(|value| value.parse::<i64>().ok())
+        .ok_or_else(|| ApiServerError::UnexpectedError("Invalid If-Match header".to_string()))
 }
 
 // GET endpoint logic
@@ -89,49 +130,392 @@ async fn get_notification(
         tracing::error!("Invalid user id provided {:?}", user_id);
         return Err(ApiServerError::UnexpectedError("Invalid user id provided".to_string()));
     }
-    let result = app_service.notification_service.get_notification_by_user_id(user_id).await;
+    let result = app_service.notification_service.get_notification_by_user_id(parse_key(user_id)?).await;
     match result {
-        Ok(mut notification) => {
+        Ok(notification) => {
             tracing::info!("Notification: {:?}", notification);
-            for notif in notification.iter_mut() {
-                // Remove "USR#" prefix from PK
-                notif.user_id = notif.user_id.strip_prefix("USR#").unwrap_or(&notif.user_id).to_string();
-                // Remove "NTF#" prefix from SK
-                notif.notification_id = notif.notification_id.strip_prefix("NTF#").unwrap_or(&notif.notification_id).to_string();
-            }
             let resp_json = to_value(notification).expect("Failed to serialize notification");
             Ok(AxumJson(serde_json::json!({"message": "succeeded", "data": resp_json })))
         },
         Err(e) => {
             tracing::error!("get_notification Error: {:?}", e);
-            return Err(ApiServerError::InternalServerError(format!("get_notification Error: {:?}", e)))
+            Err(ApiServerError::InternalServerError(format!("get_notification Error: {:?}", e)))
+        }
+    }
+}
+
+async fn get_unread_count(
+    State(app_service): State<Arc<AppService>>,
+    claims: Claims,
+    Path(user_id): Path<String>,
+) -> Result<Json<Value>, ApiServerError> {
+    if user_id != claims.uid {
+        tracing::error!("Invalid user id provided {:?}", user_id);
+        return Err(ApiServerError::UnexpectedError("Invalid user id provided".to_string()));
+    }
+    match app_service.notification_service.get_unread_count(parse_key(user_id)?).await {
+        Ok(unread_count) => Ok(AxumJson(serde_json::json!({"message": "succeeded", "data": { "unread_count": unread_count } }))),
+        Err(e) => {
+            tracing::error!("get_unread_count Error: {:?}", e);
+            Err(ApiServerError::InternalServerError(format!("get_unread_count Error: {:?}", e)))
+        }
+    }
+}
+
+// Server-Sent Events of the user's notification changes, a client that reconnects with
+// Last-Event-ID first gets the events it missed that are still in the hub history
+async fn stream_notifications(
+    State(app_service): State<Arc<AppService>>,
+    claims: Claims,
+    Path(user_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiServerError> {
+    if user_id != claims.uid {
+        tracing::error!("Invalid user id provided {:?}", user_id);
+        return Err(ApiServerError::UnexpectedError("Invalid user id provided".to_string()));
+    }
+    let last_event_id = headers.get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    let HubSubscription { guard, replay, receiver } = app_service.notification_hub
+        .subscribe(&user_id, last_event_id)
+        .ok_or_else(|| ApiServerError::TooManyRequests("Too many notification streams open".to_string()))?;
+
+    // a lagging receiver ends the stream, the client reconnects and resumes from the history
+    let live = BroadcastStream::new(receiver)
+        .map_while(|result| result.ok());
+    let stream = tokio_stream::iter(replay).chain(live).map(move |hub_event| {
+        // the guard lives as long as the stream and frees the connection slot on disconnect
+        let _ = &guard;
+        Ok(Event::default()
+            .id(hub_event.id.to_string())
+            .event(hub_event.event.kind())
+            .data(serde_json::to_string(&hub_event.event).unwrap_or_default()))
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::new().interval(Duration::from_secs(HEARTBEAT_SECONDS))))
+}
+
+#[derive(Deserialize, Debug)]
+struct SocketParams {
+    // same as Last-Event-ID on the SSE stream, WebSocket clients cannot always set headers
+    last_event_id: Option<u64>,
+}
+
+// Commands a WebSocket client sends as JSON text frames
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
+enum SocketCommand {
+    // `ACK` is the client confirming it showed the notification, which marks it read
+    #[serde(alias = "ACK")]
+    MarkRead {
+        notification_id: String,
+        // optional, like If-Match on the HTTP update
+        #[serde(default)]
+        version: Option<i64>,
+    },
+    Subscribe {
+        topic_id: String,
+    },
+}
+
+// Topics a socket follows. New messages on them are pushed as TOPIC_EVENT even when the notification
+// is another user's, once per message however many recipients it went to. They are not replayed on resume
+#[derive(Debug, Default)]
+struct SocketTopics {
+    topic_ids: HashSet<String>,
+    recent_message_ids: VecDeque<String>,
+}
+
+impl SocketTopics {
+    // The user's own event, remembered so the same message is not announced on its topic as well
+    fn own_event(&mut self, event: &NotificationEvent) {
+        if let NotificationEvent::Created(notification) = event {
+            self.first_sighting(&notification.message_id);
+        }
+    }
+
+    // Another user's event, a TOPIC_EVENT when it is a new message on a followed topic
+    fn topic_event(&mut self, hub_event: &HubEvent) -> Option<Message> {
+        let notification = match &hub_event.event {
+            NotificationEvent::Created(notification) if self.topic_ids.contains(&notification.topic_id) => notification,
+            _ => return None,
+        };
+        if !self.first_sighting(&notification.message_id) {
+            return None;
+        }
+        Some(Message::Text(json!({"type": "TOPIC_EVENT", "id": hub_event.id, "data": {
+            "topic_id": notification.topic_id,
+            "message_id": notification.message_id,
+            "replyer_id": notification.replyer_id,
+            "replyer_name": notification.replyer_name,
+            "replyer_avatar": notification.replyer_avatar,
+            "created_time": notification.created_time,
+        }}).to_string()))
+    }
+
+    fn first_sighting(&mut self, message_id: &str) -> bool {
+        if self.recent_message_ids.iter().any(|seen| seen == message_id) {
+            return false;
+        }
+        if self.recent_message_ids.len() == SOCKET_RECENT_MESSAGES {
+            self.recent_message_ids.pop_front();
+        }
+        self.recent_message_ids.push_back(message_id.to_string());
+        true
+    }
+}
+
+// WebSocket alternative to the SSE stream, pushes the same events and takes commands back
+async fn notification_socket(
+    State(app_service): State<Arc<AppService>>,
+    claims: Claims,
+    Path(user_id): Path<String>,
+    Query(params): Query<SocketParams>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, ApiServerError> {
+    if user_id != claims.uid {
+        tracing::error!("Invalid user id provided {:?}", user_id);
+        return Err(ApiServerError::UnexpectedError("Invalid user id provided".to_string()));
+    }
+    let user_key: UserKey = parse_key(user_id.clone())?;
+    let subscription = app_service.notification_hub
+        .subscribe(&user_id, params.last_event_id)
+        .ok_or_else(|| ApiServerError::TooManyRequests("Too many notification streams open".to_string()))?;
+
+    Ok(ws.on_upgrade(move |socket| handle_notification_socket(socket, app_service, user_key, subscription)))
+}
+
+async fn handle_notification_socket(mut socket: WebSocket, app_service: Arc<AppService>, user_key: UserKey, subscription: HubSubscription) {
+    // held until the socket closes to keep the connection slot
+    let HubSubscription { guard: _guard, replay, mut receiver } = subscription;
+    let mut topics = SocketTopics::default();
+    match app_service.notification_service.get_topic_subscriptions(user_key.clone()).await {
+        Ok(topic_ids) => topics.topic_ids.extend(topic_ids),
+        // the socket still carries the user's own events, SUBSCRIBE adds topics again
+        Err(e) => tracing::error!("get_topic_subscriptions Error: {:?}", e),
+    }
+    for hub_event in replay.iter() {
+        topics.own_event(&hub_event.event);
+        if socket.send(socket_event(hub_event)).await.is_err() {
+            return;
+        }
+    }
+
+    // one stream per followed topic, so the socket only gets the topics' traffic besides its own
+    let mut topic_streams = StreamMap::new();
+    follow_topics(&app_service.notification_hub, &topics, &mut topic_streams);
+
+    let mut heartbeat = tokio::time::interval(Duration::from_secs(HEARTBEAT_SECONDS));
+    loop {
+        tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let reply = handle_socket_command(&app_service.notification_service, &user_key, &mut topics, &text).await;
+                    follow_topics(&app_service.notification_hub, &topics, &mut topic_streams);
+                    if socket.send(Message::Text(reply.to_string())).await.is_err() {
+                        break;
+                    }
+                },
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                // pings are answered by axum, binary frames are not part of the protocol
+                Some(Ok(_)) => {},
+            },
+            hub_event = receiver.recv() => match hub_event {
+                Ok(hub_event) => {
+                    topics.own_event(&hub_event.event);
+                    if socket.send(socket_event(&hub_event)).await.is_err() {
+                        break;
+                    }
+                },
+                // like the SSE stream, a lagging client reconnects with `last_event_id` to catch up
+                Err(RecvError::Lagged(_)) | Err(RecvError::Closed) => break,
+            },
+            // topic events are not replayed, a lagging topic stream just skips ahead
+            Some((_, Ok(hub_event))) = topic_streams.next(), if !topic_streams.is_empty() => {
+                // the user's own notifications come on their own channel
+                if hub_event.event.user_id() == user_key.id() {
+                    continue;
+                }
+                if let Some(message) = topics.topic_event(&hub_event) {
+                    if socket.send(message).await.is_err() {
+                        break;
+                    }
+                }
+            },
+            _ = heartbeat.tick() => {
+                if socket.send(Message::Ping(vec![])).await.is_err() {
+                    break;
+                }
+            },
+        }
+    }
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+// Opens a hub stream for every followed topic that has none yet
+fn follow_topics(hub: &NotificationHub, topics: &SocketTopics, topic_streams: &mut StreamMap<String, BroadcastStream<HubEvent>>) {
+    for topic_id in topics.topic_ids.iter() {
+        if !topic_streams.contains_key(topic_id) {
+            topic_streams.insert(topic_id.clone(), BroadcastStream::new(hub.follow_topic(topic_id)));
         }
     }
 }
 
+fn socket_event(hub_event: &HubEvent) -> Message {
+    Message::Text(json!({"type": "EVENT", "id": hub_event.id, "event": hub_event.event}).to_string())
+}
+
+async fn handle_socket_command(notification_service: &SharedNotificationService, user_key: &UserKey, topics: &mut SocketTopics, text: &str) -> Value {
+    let command = match serde_json::from_str::<SocketCommand>(text) {
+        Ok(command) => command,
+        Err(e) => return json!({"type": "ERROR", "code": "INVALID_COMMAND", "message": e.to_string()}),
+    };
+    let result = match command {
+        SocketCommand::MarkRead { notification_id, version } => {
+            let noti_key = match NotificationKey::new(notification_id) {
+                Ok(noti_key) => noti_key,
+                Err(e) => return json!({"type": "ERROR", "code": "INVALID_COMMAND", "message": e.to_string()}),
+            };
+            let payload = UpdateNotificationBody { action: NotificationStatus::READ, expected_version: version };
+            notification_service.update_notification_message(user_key.clone(), noti_key, payload).await
+                .map(|notification| json!({"type": "MARK_READ", "data": notification}))
+        },
+        SocketCommand::Subscribe { topic_id } => {
+            // a topic's events carry its participants' names and messages, only they may follow it
+            match notification_service.may_follow_topic(user_key.clone(), topic_id.clone()).await {
+                Ok(true) => {},
+                Ok(false) => return json!({"type": "ERROR", "code": "FORBIDDEN", "message": format!("not a participant of topic {}", topic_id)}),
+                Err(e) => return socket_error(e),
+            }
+            notification_service.subscribe_topic(user_key.clone(), topic_id.clone()).await
+                .map(|_| {
+                    let reply = json!({"type": "SUBSCRIBED", "data": { "topic_id": topic_id }});
+                    topics.topic_ids.insert(topic_id);
+                    reply
+                })
+        },
+    };
+    result.unwrap_or_else(socket_error)
+}
+
+fn socket_error(e: ApplicationError) -> Value {
+    tracing::error!("notification socket command Error: {:?}", e);
+    let (code, message) = match e {
+        ApplicationError::PreconditionFailedError(e) => ("PRECONDITION_FAILED", e.message),
+        ApplicationError::ConflictError(e) => ("CONFLICT", e.message),
+        ApplicationError::NotFoundError(e) => ("NOT_FOUND", e.message),
+        ApplicationError::PermanentError(e) => ("INVALID_COMMAND", e.message),
+        ApplicationError::RetryableError(e) => ("INTERNAL", e.message),
+    };
+    json!({"type": "ERROR", "code": code, "message": message})
+}
 
 // Struct to capture the POST request body
 #[derive(Serialize, Deserialize, Debug)]
 pub struct UpdateNotificationBody {
     pub action: NotificationStatus,
+    // taken from the If-Match header, not the body
+    #[serde(skip)]
+    pub expected_version: Option<i64>,
 }
 
 async fn update_notification_status(
     State(app_service): State<Arc<AppService>>,
     claims: Claims,
     Path((user_id, noti_id)): Path<(String, String)>,
-    Json(payload): Json<UpdateNotificationBody>,
-) ->  Result<Json<Value>, ApiServerError> {
+    headers: HeaderMap,
+    Json(mut payload): Json<UpdateNotificationBody>,
+) ->  Result<Response, ApiServerError> {
+    if user_id != claims.uid {
+        tracing::error!("Invalid user id provided {:?}", user_id);
+        return Err(ApiServerError::UnexpectedError("Invalid user id provided".to_string()));
+    }
+    payload.expected_version = match headers.get(header::IF_MATCH) {
+        // `*` matches any existing item, which the update requires anyway
+        Some(value) if value == "*" => None,
+        Some(value) => Some(parse_etag(value)?),
+        None => None,
+    };
+    let result = app_service.notification_service.update_notification_message(parse_key(user_id)?, parse_key(noti_id)?, payload).await;
+    match result {
+        Ok(notification) => {
+            let etag = etag(notification.version);
+            let resp_json = to_value(notification).expect("Failed to serialize notification");
+            Ok(([(header::ETAG, etag)], AxumJson(serde_json::json!({"message": "succeeded", "data": resp_json }))).into_response())
+        },
+        Err(ApplicationError::PreconditionFailedError(e)) => {
+            tracing::error!("update_notification_status PreconditionFailed: {:?}", e);
+            Err(ApiServerError::PreconditionFailed(e.message))
+        },
+        Err(ApplicationError::ConflictError(e)) => {
+            tracing::error!("update_notification_status Conflict: {:?}", e);
+            Err(ApiServerError::Conflict(e.message))
+        },
+        Err(ApplicationError::NotFoundError(e)) => {
+            tracing::error!("update_notification_status NotFound: {:?}", e);
+            Err(ApiServerError::NotFound(e.message))
+        },
+        Err(e) => {
+            tracing::error!("update_notification_status Error: {:?}", e);
+            Err(ApiServerError::InternalServerError(format!("update_notification_status Error: {:?}", e)))
+        },
+    }
+}
+
+// Validated bulk update, `ids` and `before` narrow down the notifications to update
+#[derive(Debug)]
+pub struct BulkUpdateNotificationBody {
+    pub action: NotificationStatus,
+    pub ids: Option<Vec<NotificationKey>>,
+    pub before: Option<DateTime<FixedOffset>>,
+}
+
+// Struct to capture the bulk POST request body
+#[derive(Deserialize, Debug)]
+struct BulkUpdateNotificationRequest {
+    action: NotificationStatus,
+    ids: Option<Vec<String>>,
+    before: Option<String>,
+}
+
+async fn bulk_update_notification_status(
+    State(app_service): State<Arc<AppService>>,
+    claims: Claims,
+    Path(user_id): Path<String>,
+    Json(request): Json<BulkUpdateNotificationRequest>,
+) -> Result<Json<Value>, ApiServerError> {
     if user_id != claims.uid {
         tracing::error!("Invalid user id provided {:?}", user_id);
         return Err(ApiServerError::UnexpectedError("Invalid user id provided".to_string()));
     }
-    let result = app_service.notification_service.update_notification_message(user_id, noti_id, payload).await;
-    if let Err(e) = result {
-        tracing::error!("update_notification_status Error: {:?}", e);
-        return Err(ApiServerError::InternalServerError(format!("update_notification_status Error: {:?}", e)))
+    if request.action == NotificationStatus::UNREAD {
+        return Err(ApiServerError::UnexpectedError("Bulk action must be READ or REMOVED".to_string()));
+    }
+    let before = match request.before {
+        Some(before) => Some(DateTime::parse_from_rfc3339(&before)
+            .map_err(|_| ApiServerError::UnexpectedError("before must be an RFC 3339 timestamp".to_string()))?),
+        None => None,
+    };
+    let ids = match request.ids {
+        Some(ids) => Some(ids.into_iter().map(parse_key).collect::<Result<Vec<NotificationKey>, _>>()?),
+        None => None,
+    };
+    let payload = BulkUpdateNotificationBody {
+        action: request.action,
+        ids,
+        before,
+    };
+    match app_service.notification_service.bulk_update_notification_messages(parse_key(user_id)?, payload).await {
+        Ok(outcomes) => {
+            let resp_json = to_value(outcomes).expect("Failed to serialize bulk update outcomes");
+            Ok(AxumJson(serde_json::json!({"message": "succeeded", "data": resp_json })))
+        },
+        Err(e) => {
+            tracing::error!("bulk_update_notification_status Error: {:?}", e);
+            Err(ApiServerError::InternalServerError(format!("bulk_update_notification_status Error: {:?}", e)))
+        }
     }
-    Ok(AxumJson(serde_json::json!({"message": "succeeded", "data": "asd" })))
 }
 
 async fn print_request_response(
@@ -144,6 +528,13 @@ async fn print_request_response(
     let req = Request::from_parts(req_parts, body);
     let res = next.run(req).await;
 
+    // event streams never end, log them without buffering the body
+    let is_stream = res.headers().get(header::CONTENT_TYPE).map(|value| value.as_bytes().starts_with(b"text/event-stream")).unwrap_or(false);
+    if is_stream {
+        tracing::info!(method = clone_req_parts.method.to_string(), uri = clone_req_parts.uri.to_string(), status = res.status().to_string(), "response: event stream");
+        return Ok(res);
+    }
+
     let (res_parts, body) = res.into_parts();
     let clone_res_parts = res_parts.clone();
     let bytes = buffer_and_print(body, clone_req_parts, clone_res_parts).await?;
@@ -198,6 +589,10 @@ impl IntoResponse for ApiServerError {
         let (status, error_message) = match self {
             ApiServerError::InternalServerError(message) => (StatusCode::INTERNAL_SERVER_ERROR, message),
             ApiServerError::UnexpectedError(message) => (StatusCode::NOT_ACCEPTABLE, message),
+            ApiServerError::Conflict(message) => (StatusCode::CONFLICT, message),
+            ApiServerError::NotFound(message) => (StatusCode::NOT_FOUND, message),
+            ApiServerError::PreconditionFailed(message) => (StatusCode::PRECONDITION_FAILED, message),
+            ApiServerError::TooManyRequests(message) => (StatusCode::TOO_MANY_REQUESTS, message),
         };
         let body = Json(json!({
             "error": error_message,
@@ -222,7 +617,7 @@ where
         let mut validation = Validation::default();
         // Todo: update to true
         validation.validate_exp = false;
-        let token_data = decode::<Claims>(bearer.token(), &KEYS.decoding, &validation);
+        let token_data = decode::<Claims>(bearer.token(), &KEYS.get().expect("router constructed without JWT keys").decoding, &validation);
         match token_data {
             Ok(token_data) => {
                 Ok(token_data.claims)
@@ -235,3 +630,86 @@ where
 
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use crate::{
+        adapters::memo_events::processors::model::{CreateMessageBody, DBNotifcation, BulkUpdateOutcome},
+        client::eventbridge_client::EventPublisherInterface,
+        services::{archive::ArchiveSinkInterface, notification::NotificationServiceInterface},
+    };
+
+    // Knows which topics the user takes part in and records subscriptions, nothing else is called
+    #[derive(Default)]
+    struct TopicService {
+        participant_of: HashSet<String>,
+        subscribed: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl NotificationServiceInterface for TopicService {
+        async fn create_notification_message(&self, _: CreateMessageBody) -> Result<(), ApplicationError> { unimplemented!() }
+        async fn create_fan_out_notification_messages(&self, _: CreateMessageBody) -> Result<usize, ApplicationError> { unimplemented!() }
+        async fn get_notification_by_user_id(&self, _: UserKey) -> Result<Vec<DBNotifcation>, ApplicationError> { unimplemented!() }
+        async fn update_notification_message(&self, _: UserKey, _: NotificationKey, _: UpdateNotificationBody) -> Result<DBNotifcation, ApplicationError> { unimplemented!() }
+        async fn get_unread_count(&self, _: UserKey) -> Result<i64, ApplicationError> { unimplemented!() }
+        async fn reconcile_unread_counts(&self) -> Result<usize, ApplicationError> { unimplemented!() }
+        async fn bulk_update_notification_messages(&self, _: UserKey, _: BulkUpdateNotificationBody) -> Result<Vec<BulkUpdateOutcome>, ApplicationError> { unimplemented!() }
+        async fn archive_expiring_notifications(&self, _: &(dyn ArchiveSinkInterface + Send + Sync), _: i64) -> Result<usize, ApplicationError> { unimplemented!() }
+        async fn relay_outbox_events(&self, _: &(dyn EventPublisherInterface + Send + Sync)) -> Result<usize, ApplicationError> { unimplemented!() }
+        async fn get_topic_subscriptions(&self, _: UserKey) -> Result<Vec<String>, ApplicationError> { unimplemented!() }
+
+        async fn subscribe_topic(&self, _: UserKey, topic_id: String) -> Result<(), ApplicationError> {
+            self.subscribed.lock().unwrap().push(topic_id);
+            Ok(())
+        }
+
+        async fn may_follow_topic(&self, _: UserKey, topic_id: String) -> Result<bool, ApplicationError> {
+            Ok(self.participant_of.contains(&topic_id))
+        }
+    }
+
+    fn created(id: u64, user_id: &str, topic_id: &str, message_id: &str) -> HubEvent {
+        let notification = serde_json::from_value(json!({
+            "SK": format!("{}-{}", message_id, user_id), "PK": user_id,
+            "replyer_id": "replyer", "replyer_avatar": "", "replyer_name": "Replyer",
+            "notification_type": "Message", "status": "UNREAD",
+            "topic_id": topic_id, "message_id": message_id, "content": "hello",
+            "created_time": "2024-01-01T00:00:00+00:00", "updated_time": null,
+        })).unwrap();
+        HubEvent { id, event: NotificationEvent::Created(Box::new(notification)) }
+    }
+
+    #[test]
+    fn announces_each_message_on_a_followed_topic_once() {
+        let mut topics = SocketTopics::default();
+        topics.topic_ids.insert("followed".to_string());
+
+        assert!(topics.topic_event(&created(1, "user-2", "other", "message-1")).is_none());
+        assert!(topics.topic_event(&created(2, "user-2", "followed", "message-2")).is_some());
+        // the same message fanned out to another recipient
+        assert!(topics.topic_event(&created(3, "user-3", "followed", "message-2")).is_none());
+        // the user got the message as their own notification first
+        topics.own_event(&created(4, "user-1", "followed", "message-3").event);
+        assert!(topics.topic_event(&created(5, "user-2", "followed", "message-3")).is_none());
+    }
+
+    #[tokio::test]
+    async fn only_participants_subscribe_to_a_topic() {
+        let service = Arc::new(TopicService { participant_of: HashSet::from(["mine".to_string()]), ..Default::default() });
+        let notification_service: SharedNotificationService = service.clone();
+        let user_key = UserKey::new("user-1".to_string()).unwrap();
+        let mut topics = SocketTopics::default();
+
+        let denied = handle_socket_command(&notification_service, &user_key, &mut topics, r#"{"type": "SUBSCRIBE", "topic_id": "theirs"}"#).await;
+        assert_eq!(denied["type"], "ERROR");
+        assert_eq!(denied["code"], "FORBIDDEN");
+        let allowed = handle_socket_command(&notification_service, &user_key, &mut topics, r#"{"type": "SUBSCRIBE", "topic_id": "mine"}"#).await;
+        assert_eq!(allowed["type"], "SUBSCRIBED");
+
+        assert_eq!(*service.subscribed.lock().unwrap(), vec!["mine".to_string()]);
+        assert_eq!(topics.topic_ids, HashSet::from(["mine".to_string()]));
+    }
+}
@@ -0,0 +1,31 @@
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use std::collections::HashMap;
+use tracing::Instrument;
+
+use crate::telemetry;
+
+// One server span per request, continuing the caller's trace when it sends `traceparent`
+pub async fn trace_http_request(req: Request, next: Next) -> Response {
+    let route = req.extensions().get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let span = tracing::info_span!(
+        "http.request",
+        otel.name = %format!("{} {}", req.method(), route),
+        otel.kind = "server",
+        http.request.method = %req.method(),
+        http.route = %route,
+        http.response.status_code = tracing::field::Empty,
+    );
+    let carrier: HashMap<String, String> = ["traceparent", "tracestate"].iter()
+        .filter_map(|name| req.headers().get(*name).and_then(|value| value.to_str().ok()).map(|value| (name.to_string(), value.to_string())))
+        .collect();
+    telemetry::set_remote_parent(&span, &carrier);
+    let res = next.run(req).instrument(span.clone()).await;
+    span.record("http.response.status_code", res.status().as_u16());
+    res
+}
@@ -0,0 +1,256 @@
+use std::{collections::{HashMap, HashSet}, time::{Duration, Instant}};
+use aws_sdk_dynamodb::{Client as DynamoDbClient, Error as DynamoDbError, types::AttributeValue};
+use aws_sdk_dynamodbstreams::{
+    Client as DynamoDbStreamsClient, Error as DynamoDbStreamsError,
+    types::{AttributeValue as StreamAttributeValue, OperationType, Record, ShardIteratorType},
+};
+use tokio::time::sleep;
+
+use crate::{
+    adapters::memo_events::processors::model::DBNotifcation,
+    errors::main::{SerializationError, SystemError},
+    services::{
+        bus::{NotificationEvent, SharedNotificationBus},
+        keys::{EntityKey, UserKey, NotificationKey, CounterKey},
+    },
+};
+
+// New shards appear when DynamoDB splits or rotates them, roughly every few hours
+const SHARD_DISCOVERY_INTERVAL: Duration = Duration::from_secs(30);
+
+pub struct DynamoDbStreamConsumerOption {
+    pub dynamodb_client: DynamoDbClient,
+    pub streams_client: DynamoDbStreamsClient,
+    pub table_name: String,
+    pub notification_bus: SharedNotificationBus,
+    pub interval_millis: Option<u64>,
+}
+
+// Turns the table's stream into bus events, so this process sees changes written by any other one.
+// Reading starts at the latest record, nothing is checkpointed since the events only drive live push and cache invalidation
+pub struct DynamoDbStreamConsumer {
+    dynamodb_client: DynamoDbClient,
+    streams_client: DynamoDbStreamsClient,
+    table_name: String,
+    notification_bus: SharedNotificationBus,
+    interval_millis: u64,
+}
+
+struct ShardCursor {
+    iterator: Option<String>,
+    last_sequence_number: Option<String>,
+}
+
+impl DynamoDbStreamConsumer {
+    pub fn new(option: DynamoDbStreamConsumerOption) -> Self {
+        DynamoDbStreamConsumer {
+            dynamodb_client: option.dynamodb_client,
+            streams_client: option.streams_client,
+            table_name: option.table_name,
+            notification_bus: option.notification_bus,
+            interval_millis: option.interval_millis.unwrap_or(1000),
+        }
+    }
+
+    pub async fn start(&self) {
+        loop {
+            if let Err(e) = self.consume().await {
+                tracing::error!("dynamodb stream consumer Error: {:?}", e);
+            }
+            sleep(Duration::from_millis(self.interval_millis)).await;
+        }
+    }
+
+    async fn consume(&self) -> Result<(), SystemError> {
+        let stream_arn = self.stream_arn().await?;
+        tracing::info!("Consuming changes from stream {}", stream_arn);
+        let mut cursors: HashMap<String, ShardCursor> = HashMap::new();
+        let mut finished: HashSet<String> = HashSet::new();
+        let mut discovered_at: Option<Instant> = None;
+        loop {
+            if discovered_at.map(|at| at.elapsed() >= SHARD_DISCOVERY_INTERVAL).unwrap_or(true) {
+                let starting = discovered_at.is_none();
+                for (shard_id, closed) in self.list_shards(&stream_arn).await? {
+                    if cursors.contains_key(&shard_id) || finished.contains(&shard_id) {
+                        continue;
+                    }
+                    // history before startup is of no use, but shards found later are children of ones
+                    // being read and are read from the start so no change is skipped
+                    let iterator_type = match (starting, closed) {
+                        (true, true) => {
+                            finished.insert(shard_id);
+                            continue;
+                        },
+                        (true, false) => ShardIteratorType::Latest,
+                        (false, _) => ShardIteratorType::TrimHorizon,
+                    };
+                    let iterator = self.shard_iterator(&stream_arn, &shard_id, iterator_type, None).await?;
+                    cursors.insert(shard_id, ShardCursor { iterator, last_sequence_number: None });
+                }
+                discovered_at = Some(Instant::now());
+            }
+
+            let mut received = 0;
+            for (shard_id, cursor) in cursors.iter_mut() {
+                let iterator = match &cursor.iterator {
+                    Some(iterator) => iterator.clone(),
+                    None => continue,
+                };
+                let output = match self.streams_client.get_records().shard_iterator(iterator).send().await {
+                    Ok(output) => output,
+                    Err(e) => {
+                        // iterators expire after 15 minutes, pick up after the last record seen
+                        tracing::error!("get_records on shard {} Error: {:?}", shard_id, DynamoDbStreamsError::from(e));
+                        cursor.iterator = match &cursor.last_sequence_number {
+                            Some(sequence_number) => self.shard_iterator(&stream_arn, shard_id, ShardIteratorType::AfterSequenceNumber, Some(sequence_number.clone())).await?,
+                            None => self.shard_iterator(&stream_arn, shard_id, ShardIteratorType::Latest, None).await?,
+                        };
+                        continue;
+                    }
+                };
+                for record in output.records() {
+                    if let Some(sequence_number) = record.dynamodb().and_then(|change| change.sequence_number()) {
+                        cursor.last_sequence_number = Some(sequence_number.to_string());
+                    }
+                    if let Some(event) = change_event(record) {
+                        self.notification_bus.publish(event).await;
+                    }
+                }
+                received += output.records().len();
+                cursor.iterator = output.next_shard_iterator().map(|iterator| iterator.to_string());
+            }
+
+            // a shard without a next iterator is closed and fully read
+            cursors.retain(|shard_id, cursor| {
+                if cursor.iterator.is_none() {
+                    finished.insert(shard_id.clone());
+                }
+                cursor.iterator.is_some()
+            });
+            if received == 0 {
+                sleep(Duration::from_millis(self.interval_millis)).await;
+            }
+        }
+    }
+
+    async fn stream_arn(&self) -> Result<String, SystemError> {
+        let result = self.dynamodb_client.describe_table()
+            .table_name(self.table_name.clone())
+            .send()
+            .await
+            .map_err(DynamoDbError::from)?;
+        result.table()
+            .and_then(|table| table.latest_stream_arn())
+            .map(|stream_arn| stream_arn.to_string())
+            .ok_or_else(|| SerializationError::new(&format!("table {} has no stream, run MIGRATE to enable it", self.table_name)).into())
+    }
+
+    // Shard ids with whether the shard is closed
+    async fn list_shards(&self, stream_arn: &str) -> Result<Vec<(String, bool)>, SystemError> {
+        let mut shards = vec![];
+        let mut exclusive_start_shard_id = None;
+        loop {
+            let result = self.streams_client.describe_stream()
+                .stream_arn(stream_arn)
+                .set_exclusive_start_shard_id(exclusive_start_shard_id)
+                .send()
+                .await
+                .map_err(DynamoDbStreamsError::from)?;
+            let description = match result.stream_description() {
+                Some(description) => description,
+                None => break,
+            };
+            for shard in description.shards() {
+                if let Some(shard_id) = shard.shard_id() {
+                    let closed = shard.sequence_number_range().and_then(|range| range.ending_sequence_number()).is_some();
+                    shards.push((shard_id.to_string(), closed));
+                }
+            }
+            exclusive_start_shard_id = description.last_evaluated_shard_id().map(|shard_id| shard_id.to_string());
+            if exclusive_start_shard_id.is_none() {
+                break;
+            }
+        }
+
+        Ok(shards)
+    }
+
+    async fn shard_iterator(&self, stream_arn: &str, shard_id: &str, iterator_type: ShardIteratorType, sequence_number: Option<String>) -> Result<Option<String>, SystemError> {
+        let result = self.streams_client.get_shard_iterator()
+            .stream_arn(stream_arn)
+            .shard_id(shard_id)
+            .shard_iterator_type(iterator_type)
+            .set_sequence_number(sequence_number)
+            .send()
+            .await
+            .map_err(DynamoDbStreamsError::from)?;
+
+        Ok(result.shard_iterator().map(|iterator| iterator.to_string()))
+    }
+}
+
+// Notification and unread counter changes, other items (source mappings, subscriptions, outbox) are not pushed
+fn change_event(record: &Record) -> Option<NotificationEvent> {
+    let change = record.dynamodb()?;
+    let keys = change.keys()?;
+    let user_key = match keys.get("PK") {
+        Some(StreamAttributeValue::S(pk)) => UserKey::decode(pk).ok()?,
+        _ => return None,
+    };
+    let sk = match keys.get("SK") {
+        Some(StreamAttributeValue::S(sk)) => sk,
+        _ => return None,
+    };
+    if *sk == CounterKey::unread().encode() {
+        return Some(NotificationEvent::CountChanged { user_id: user_key.id().to_string() });
+    }
+    let noti_key = NotificationKey::decode(sk).ok()?;
+
+    match record.event_name()? {
+        OperationType::Insert => {
+            let notification = DBNotifcation::from_item(table_item(change.new_image()?)).ok()?;
+            Some(NotificationEvent::Created(Box::new(notification)))
+        },
+        OperationType::Modify => {
+            let notification = DBNotifcation::from_item(table_item(change.new_image()?)).ok()?;
+            // writes that leave the status alone (migrations, backfills) are not changes clients care about
+            let status = format!("{:?}", notification.status);
+            if let Some(StreamAttributeValue::S(old_status)) = change.old_image().and_then(|image| image.get("status")) {
+                if *old_status == status {
+                    return None;
+                }
+            }
+            Some(NotificationEvent::StatusChanged {
+                user_id: notification.user_id,
+                notification_id: notification.notification_id,
+                status: notification.status,
+            })
+        },
+        OperationType::Remove => Some(NotificationEvent::Deleted {
+            user_id: user_key.id().to_string(),
+            notification_id: noti_key.id().to_string(),
+        }),
+        _ => None,
+    }
+}
+
+// The streams SDK has its own attribute type, convert so stream images decode like table items
+fn table_item(image: &HashMap<String, StreamAttributeValue>) -> HashMap<String, AttributeValue> {
+    image.iter().map(|(name, value)| (name.clone(), table_attribute(value))).collect()
+}
+
+fn table_attribute(value: &StreamAttributeValue) -> AttributeValue {
+    match value {
+        StreamAttributeValue::S(value) => AttributeValue::S(value.clone()),
+        StreamAttributeValue::N(value) => AttributeValue::N(value.clone()),
+        StreamAttributeValue::B(value) => AttributeValue::B(value.clone()),
+        StreamAttributeValue::Bool(value) => AttributeValue::Bool(*value),
+        StreamAttributeValue::Null(value) => AttributeValue::Null(*value),
+        StreamAttributeValue::Ss(values) => AttributeValue::Ss(values.clone()),
+        StreamAttributeValue::Ns(values) => AttributeValue::Ns(values.clone()),
+        StreamAttributeValue::Bs(values) => AttributeValue::Bs(values.clone()),
+        StreamAttributeValue::L(values) => AttributeValue::L(values.iter().map(table_attribute).collect()),
+        StreamAttributeValue::M(values) => AttributeValue::M(table_item(values)),
+        _ => AttributeValue::Null(true),
+    }
+}
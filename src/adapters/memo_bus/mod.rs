@@ -0,0 +1,2 @@
+pub mod uds_bridge;
+pub mod dynamodb_stream_consumer;
\ No newline at end of file
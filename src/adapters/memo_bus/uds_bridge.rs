@@ -0,0 +1,119 @@
+use std::{path::PathBuf, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+    sync::broadcast::error::RecvError,
+    time::sleep,
+};
+
+use crate::services::bus::{NotificationEvent, SharedNotificationBus};
+
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct UdsBusForwarderOption {
+    pub notification_bus: SharedNotificationBus,
+    pub socket_path: PathBuf,
+}
+
+// Writes every change on the local bus to the socket as one JSON line, reconnecting when the listener goes away.
+// Changes made while disconnected are not replayed, the listener side falls back to cache expiry
+pub struct UdsBusForwarder {
+    notification_bus: SharedNotificationBus,
+    socket_path: PathBuf,
+}
+
+impl UdsBusForwarder {
+    pub fn new(option: UdsBusForwarderOption) -> Self {
+        UdsBusForwarder {
+            notification_bus: option.notification_bus,
+            socket_path: option.socket_path,
+        }
+    }
+
+    pub async fn start(&self) {
+        loop {
+            let mut stream = match UnixStream::connect(&self.socket_path).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    tracing::debug!("change bus socket {:?} not available: {:?}", self.socket_path, e);
+                    sleep(RECONNECT_INTERVAL).await;
+                    continue;
+                }
+            };
+            tracing::info!("Forwarding changes to {:?}", self.socket_path);
+            // subscribed per connection, so nothing buffered while disconnected is sent late
+            let mut receiver = self.notification_bus.subscribe();
+            loop {
+                let event = match receiver.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(missed)) => {
+                        tracing::error!("change bus forwarder missed {} events", missed);
+                        continue;
+                    },
+                    Err(RecvError::Closed) => return,
+                };
+                let mut line = match serde_json::to_vec(&event) {
+                    Ok(line) => line,
+                    Err(e) => {
+                        tracing::error!("Failed to serialize change {:?}: {:?}", event, e);
+                        continue;
+                    }
+                };
+                line.push(b'\n');
+                if let Err(e) = stream.write_all(&line).await {
+                    tracing::error!("change bus socket write Error: {:?}", e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+pub struct UdsBusListenerOption {
+    pub notification_bus: SharedNotificationBus,
+    pub socket_path: PathBuf,
+}
+
+// Accepts forwarders on the socket and publishes what they send on the local bus
+pub struct UdsBusListener {
+    notification_bus: SharedNotificationBus,
+    socket_path: PathBuf,
+}
+
+impl UdsBusListener {
+    pub fn new(option: UdsBusListenerOption) -> Self {
+        UdsBusListener {
+            notification_bus: option.notification_bus,
+            socket_path: option.socket_path,
+        }
+    }
+
+    pub async fn start(&self) -> std::io::Result<()> {
+        // a socket file left by a previous run would make bind fail
+        if self.socket_path.exists() {
+            std::fs::remove_file(&self.socket_path)?;
+        }
+        let listener = UnixListener::bind(&self.socket_path)?;
+        tracing::info!("Listening for changes on {:?}", self.socket_path);
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let notification_bus = self.notification_bus.clone();
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(stream).lines();
+                loop {
+                    match lines.next_line().await {
+                        Ok(Some(line)) => match serde_json::from_str::<NotificationEvent>(&line) {
+                            Ok(event) => notification_bus.publish(event).await,
+                            Err(e) => tracing::error!("Invalid change on bus socket {:?}: {:?}", line, e),
+                        },
+                        Ok(None) => break,
+                        Err(e) => {
+                            tracing::error!("change bus socket read Error: {:?}", e);
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+    }
+}
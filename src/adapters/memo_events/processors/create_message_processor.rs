@@ -1,7 +1,6 @@
 use async_trait::async_trait;
 
 use crate::{
-  services::notification::NotificationServiceInterface,
   adapters::memo_events::processors::event_type_processor::{EventTypeProcessorInterface, ApplicationError, PermanentError },
   adapters::memo_events::processors::model::{CreateMessageProcessor, CreateMessageProcessorOption, CreateMessageBody}
 };
@@ -11,7 +10,7 @@ impl EventTypeProcessorInterface for CreateMessageProcessor {
   type Input = CreateMessageProcessorOption;
 
   fn new(input: Self::Input) -> Self {
-    eprintln!("CreateMessageProcessor::new {:?}", input);
+    eprintln!("CreateMessageProcessor::new {:?}", input.event_type);
     CreateMessageProcessor {
       event_type: input.event_type,
       notification_service: input.notification_service,
@@ -22,8 +21,12 @@ impl EventTypeProcessorInterface for CreateMessageProcessor {
     // let parsed: CreateMessageBody = serde_json::from_str(input.body.as_str()).expect("CreateMessageBody was not well-formatted");
     // .expect("CreateMessageBody was not well-formatted");
     let parsed: CreateMessageBody = serde_json::from_str(body.as_str()).map_err(|e| ApplicationError::PermanentError(PermanentError{ message: format!("CreateMessageBody was not well-formatted {:?}", e) }))?;
-    tracing::info!("CreateMessageProcessor::process {:?}", parsed);
-    self.notification_service.create_notification_message(parsed).await?;
+    tracing::info!("CreateMessageProcessor::process {:?} {:?}", self.event_type, parsed);
+    if parsed.detail.recipient_ids.is_some() {
+      self.notification_service.create_fan_out_notification_messages(parsed).await?;
+    } else {
+      self.notification_service.create_notification_message(parsed).await?;
+    }
     Ok(())
   }
 }
\ No newline at end of file
@@ -43,10 +43,77 @@ impl fmt::Display for RetryableError {
 
 impl std::error::Error for RetryableError {}
 
+#[derive(Debug)]
+pub struct ConflictError {
+    pub message: String,
+}
+
+impl ConflictError {
+    pub fn new(message: &str) -> Self {
+        ConflictError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ConflictError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Conflict Error: {}", self.message)
+    }
+}
+
+impl std::error::Error for ConflictError {}
+
+#[derive(Debug)]
+pub struct NotFoundError {
+    pub message: String,
+}
+
+impl NotFoundError {
+    pub fn new(message: &str) -> Self {
+        NotFoundError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for NotFoundError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Not Found Error: {}", self.message)
+    }
+}
+
+impl std::error::Error for NotFoundError {}
+
+#[derive(Debug)]
+pub struct PreconditionFailedError {
+    pub message: String,
+}
+
+impl PreconditionFailedError {
+    pub fn new(message: &str) -> Self {
+        PreconditionFailedError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for PreconditionFailedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Precondition Failed Error: {}", self.message)
+    }
+}
+
+impl std::error::Error for PreconditionFailedError {}
+
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum ApplicationError {
     RetryableError(RetryableError),
     PermanentError(PermanentError),
+    ConflictError(ConflictError),
+    NotFoundError(NotFoundError),
+    PreconditionFailedError(PreconditionFailedError),
 }
 
 impl From<RetryableError> for ApplicationError {
@@ -61,6 +128,24 @@ impl From<PermanentError> for ApplicationError {
     }
 }
 
+impl From<ConflictError> for ApplicationError {
+    fn from(error: ConflictError) -> Self {
+        ApplicationError::ConflictError(error)
+    }
+}
+
+impl From<NotFoundError> for ApplicationError {
+    fn from(error: NotFoundError) -> Self {
+        ApplicationError::NotFoundError(error)
+    }
+}
+
+impl From<PreconditionFailedError> for ApplicationError {
+    fn from(error: PreconditionFailedError) -> Self {
+        ApplicationError::PreconditionFailedError(error)
+    }
+}
+
 #[async_trait]
 pub trait EventTypeProcessorInterface {
     type Input;
@@ -1,13 +1,20 @@
 use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use aws_sdk_dynamodb::types::AttributeValue;
+use ulid::Ulid;
 
 use crate::{
-  services::notification::NotificationService,
+  errors::main::SerializationError,
+  utils::utils::{to_attribute_map, from_attribute_map, json_to_attribute, attribute_to_json},
+  services::keys::{EntityKey, UserKey, NotificationKey, OutboxKey, EventKey},
+  services::notification::SharedNotificationService,
   adapters::memo_events::processors::event_type::MemoEventTypes,
 };
 
 pub struct CreateMessageProcessor {
   pub event_type: MemoEventTypes,
-  pub notification_service: NotificationService,
+  pub notification_service: SharedNotificationService,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -35,27 +42,54 @@ pub struct CreateMessageDetail {
   pub topic_id: String,
   pub message_id: String,
   pub content: String,
+  // free-form payload from the producer, e.g. actor lists, stored as a DynamoDB map
+  #[serde(default)]
+  pub metadata: Option<HashMap<String, Value>>,
+  // set by fan-out events (topic followers, mentions), each recipient gets its own copy and `user_id` is ignored
+  #[serde(default)]
+  pub recipient_ids: Option<Vec<String>>,
   pub created_time: String,
 }
 
-#[derive(Debug)]
 pub struct CreateMessageProcessorOption {
   pub event_type: MemoEventTypes,
-  pub notification_service: NotificationService,
+  pub notification_service: SharedNotificationService,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub enum NotificationType {
     Message,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub enum NotificationStatus {
     READ,
     UNREAD,
     REMOVED,
 }
 
+impl NotificationStatus {
+    // UNREAD <-> READ can flip back and forth, REMOVED is terminal
+    pub fn can_transition_to(&self, next: &NotificationStatus) -> bool {
+        matches!(
+            (self, next),
+            (NotificationStatus::UNREAD, NotificationStatus::READ)
+                | (NotificationStatus::UNREAD, NotificationStatus::REMOVED)
+                | (NotificationStatus::READ, NotificationStatus::UNREAD)
+                | (NotificationStatus::READ, NotificationStatus::REMOVED)
+        )
+    }
+
+    // Statuses a notification may be in for a transition into `self` to be legal
+    pub fn allowed_predecessors(&self) -> Vec<NotificationStatus> {
+        vec![NotificationStatus::READ, NotificationStatus::UNREAD, NotificationStatus::REMOVED]
+            .into_iter()
+            .filter(|from| from.can_transition_to(self))
+            .collect()
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DBNotifcation {
     #[serde(rename = "SK")]
@@ -70,6 +104,245 @@ pub struct DBNotifcation {
     pub topic_id: String,
     pub message_id: String,
     pub content: String,
+    // stored through `json_to_attribute` by `to_item`/`from_item`, numbers keep their exact text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<HashMap<String, Value>>,
+    // the upstream `notification_id` when SK holds a generated id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_notification_id: Option<String>,
     pub created_time: String,
     pub updated_time: Option<String>,
-}
\ No newline at end of file
+    #[serde(default)]
+    pub read_at: Option<String>,
+    #[serde(default)]
+    pub removed_at: Option<String>,
+    // epoch seconds, DynamoDB TTL deletes the item some time after this
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    // bumped on every write, items written before versioning read as 0
+    #[serde(default)]
+    pub version: i64,
+}
+impl DBNotifcation {
+    // Storage form of the notification, `user_id` and `notification_id` are encoded into PK/SK
+    pub fn to_item(&self) -> Result<HashMap<String, AttributeValue>, SerializationError> {
+        let mut item = to_attribute_map(self)?;
+        item.insert("PK".to_string(), UserKey::new(self.user_id.clone())?.attribute());
+        item.insert("SK".to_string(), NotificationKey::new(self.notification_id.clone())?.attribute());
+        if let Some(metadata) = &self.metadata {
+            item.insert("metadata".to_string(), AttributeValue::M(metadata.iter().map(|(key, value)| (key.clone(), json_to_attribute(value))).collect()));
+        }
+        Ok(item)
+    }
+
+    // Decodes a stored notification back to plain ids
+    pub fn from_item(mut item: HashMap<String, AttributeValue>) -> Result<Self, SerializationError> {
+        let pk = item.get("PK").and_then(|pk| pk.as_s().ok()).ok_or_else(|| SerializationError::new("notification item without PK"))?;
+        let sk = item.get("SK").and_then(|sk| sk.as_s().ok()).ok_or_else(|| SerializationError::new("notification item without SK"))?;
+        let user_key = UserKey::decode(pk)?;
+        let notification_key = NotificationKey::decode(sk)?;
+        item.insert("PK".to_string(), AttributeValue::S(user_key.id().to_string()));
+        item.insert("SK".to_string(), AttributeValue::S(notification_key.id().to_string()));
+        let metadata = match item.remove("metadata") {
+            Some(AttributeValue::M(metadata)) => Some(metadata.iter()
+                .map(|(key, value)| Ok((key.clone(), attribute_to_json(value)?)))
+                .collect::<Result<HashMap<_, _>, SerializationError>>()?),
+            Some(other) => return Err(SerializationError::new(&format!("notification metadata is not a map: {:?}", other))),
+            None => None,
+        };
+        let mut notification: DBNotifcation = from_attribute_map(item)?;
+        notification.metadata = metadata;
+        Ok(notification)
+    }
+
+    pub fn from_items(items: Vec<HashMap<String, AttributeValue>>) -> Result<Vec<Self>, SerializationError> {
+        items.into_iter().map(DBNotifcation::from_item).collect()
+    }
+}
+
+// Domain event waiting in the outbox, written in the same transaction as the change it describes
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DBOutboxEvent {
+    #[serde(rename = "SK")]
+    pub event_id: String,
+    #[serde(rename = "PK")]
+    pub shard: String,
+    pub source: String,
+    pub detail_type: String,
+    // the event detail as a JSON document
+    pub detail: String,
+    pub created_time: String,
+}
+impl DBOutboxEvent {
+    pub fn new<T: Serialize>(user_key: &UserKey, source: &str, detail_type: &str, detail: &T) -> Result<Self, SerializationError> {
+        let event_key = EventKey::new(Ulid::new().to_string())?;
+        DBOutboxEvent::with_key(&event_key, user_key, source, detail_type, detail)
+    }
+
+    // An event whose id was chosen up front, so writing it twice is noticed
+    pub fn with_key<T: Serialize>(event_key: &EventKey, user_key: &UserKey, source: &str, detail_type: &str, detail: &T) -> Result<Self, SerializationError> {
+        let now = chrono::Utc::now();
+        Ok(DBOutboxEvent {
+            event_id: event_key.id().to_string(),
+            shard: OutboxKey::for_user(user_key).id().to_string(),
+            source: source.to_string(),
+            detail_type: detail_type.to_string(),
+            detail: serde_json::to_string(detail).map_err(|e| SerializationError::new(&e.to_string()))?,
+            created_time: now.to_rfc3339(),
+        })
+    }
+
+    pub fn to_item(&self) -> Result<HashMap<String, AttributeValue>, SerializationError> {
+        let mut item = to_attribute_map(self)?;
+        item.insert("PK".to_string(), OutboxKey::new(self.shard.clone())?.attribute());
+        item.insert("SK".to_string(), EventKey::new(self.event_id.clone())?.attribute());
+        Ok(item)
+    }
+
+    pub fn from_item(mut item: HashMap<String, AttributeValue>) -> Result<Self, SerializationError> {
+        let pk = item.get("PK").and_then(|pk| pk.as_s().ok()).ok_or_else(|| SerializationError::new("outbox item without PK"))?;
+        let sk = item.get("SK").and_then(|sk| sk.as_s().ok()).ok_or_else(|| SerializationError::new("outbox item without SK"))?;
+        let outbox_key = OutboxKey::decode(pk)?;
+        let event_key = EventKey::decode(sk)?;
+        item.insert("PK".to_string(), AttributeValue::S(outbox_key.id().to_string()));
+        item.insert("SK".to_string(), AttributeValue::S(event_key.id().to_string()));
+        from_attribute_map(item)
+    }
+
+    pub fn from_items(items: Vec<HashMap<String, AttributeValue>>) -> Result<Vec<Self>, SerializationError> {
+        items.into_iter().map(DBOutboxEvent::from_item).collect()
+    }
+}
+
+// A status change and the id of the event announcing it. A single-item update keeps it on the
+// notification as `pending_*` attributes until the event and the counter update are written
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DBStatusChange {
+    #[serde(rename = "pending_event_id")]
+    pub event_id: String,
+    #[serde(rename = "pending_from_status")]
+    pub from_status: NotificationStatus,
+    #[serde(rename = "pending_status")]
+    pub status: NotificationStatus,
+    #[serde(rename = "pending_updated_time")]
+    pub updated_time: String,
+}
+impl DBStatusChange {
+    pub const ATTRIBUTES: [&'static str; 4] = ["pending_event_id", "pending_from_status", "pending_status", "pending_updated_time"];
+
+    // None unless the notification item carries a pending change
+    pub fn from_item(item: &HashMap<String, AttributeValue>) -> Result<Option<Self>, SerializationError> {
+        if !item.contains_key("pending_event_id") {
+            return Ok(None);
+        }
+        let attributes = DBStatusChange::ATTRIBUTES.iter()
+            .filter_map(|name| item.get(*name).map(|value| (name.to_string(), value.clone())))
+            .collect();
+        Ok(Some(from_attribute_map(attributes)?))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DBUnreadCounter {
+    #[serde(rename = "SK")]
+    pub counter_id: String,
+    #[serde(rename = "PK")]
+    pub user_id: String,
+    pub unread_count: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum BulkUpdateResult {
+    Updated,
+    Unchanged,
+    NotFound,
+    Conflict,
+    Failed,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BulkUpdateOutcome {
+    pub notification_id: String,
+    pub result: BulkUpdateResult,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removed_is_terminal() {
+        use NotificationStatus::*;
+        assert!(UNREAD.can_transition_to(&READ));
+        assert!(UNREAD.can_transition_to(&REMOVED));
+        assert!(READ.can_transition_to(&UNREAD));
+        assert!(READ.can_transition_to(&REMOVED));
+        assert!(!REMOVED.can_transition_to(&UNREAD));
+        assert!(!REMOVED.can_transition_to(&READ));
+        // staying put is not a transition, callers report it as unchanged
+        assert!(!READ.can_transition_to(&READ));
+    }
+
+    #[test]
+    fn allowed_predecessors_follow_the_transitions() {
+        use NotificationStatus::*;
+        assert_eq!(READ.allowed_predecessors(), vec![UNREAD]);
+        assert_eq!(UNREAD.allowed_predecessors(), vec![READ]);
+        assert_eq!(REMOVED.allowed_predecessors(), vec![READ, UNREAD]);
+    }
+
+    #[test]
+    fn metadata_numbers_round_trip_exactly() {
+        let body = r#"{"above_2_53": 9007199254740993, "beyond_u64": 123456789012345678901234567890, "decimal": 0.1000000000000000055511151231257827, "nested": {"ids": [9007199254740995, -9007199254740995]}}"#;
+        let metadata: HashMap<String, Value> = serde_json::from_str(body).unwrap();
+        let notification = DBNotifcation {
+            notification_id: "noti-1".to_string(),
+            user_id: "user-1".to_string(),
+            replyer_id: "user-2".to_string(),
+            replyer_avatar: String::new(),
+            replyer_name: String::new(),
+            notification_type: NotificationType::Message,
+            status: NotificationStatus::UNREAD,
+            topic_id: "topic-1".to_string(),
+            message_id: "message-1".to_string(),
+            content: String::new(),
+            metadata: Some(metadata.clone()),
+            source_notification_id: None,
+            created_time: "2024-01-01T00:00:00+00:00".to_string(),
+            updated_time: None,
+            read_at: None,
+            removed_at: None,
+            expires_at: None,
+            version: 0,
+        };
+
+        let item = notification.to_item().unwrap();
+        let stored = item["metadata"].as_m().unwrap();
+        assert_eq!(stored["above_2_53"], AttributeValue::N("9007199254740993".to_string()));
+        assert_eq!(stored["beyond_u64"], AttributeValue::N("123456789012345678901234567890".to_string()));
+        assert_eq!(stored["decimal"], AttributeValue::N("0.1000000000000000055511151231257827".to_string()));
+
+        let decoded = DBNotifcation::from_item(item).unwrap();
+        assert_eq!(decoded.metadata, Some(metadata));
+        let json = serde_json::to_string(&decoded.metadata.unwrap()["beyond_u64"]).unwrap();
+        assert_eq!(json, "123456789012345678901234567890");
+    }
+
+    #[test]
+    fn pending_status_change_is_read_from_the_notification_item() {
+        let mut item: HashMap<String, AttributeValue> = HashMap::from([
+            ("status".to_string(), AttributeValue::S("READ".to_string())),
+        ]);
+        assert!(DBStatusChange::from_item(&item).unwrap().is_none());
+
+        item.insert("pending_event_id".to_string(), AttributeValue::S("01HZX".to_string()));
+        item.insert("pending_from_status".to_string(), AttributeValue::S("UNREAD".to_string()));
+        item.insert("pending_status".to_string(), AttributeValue::S("READ".to_string()));
+        item.insert("pending_updated_time".to_string(), AttributeValue::S("2024-01-01T00:00:00+00:00".to_string()));
+        let change = DBStatusChange::from_item(&item).unwrap().unwrap();
+        assert_eq!(change.event_id, "01HZX");
+        assert_eq!(change.from_status, NotificationStatus::UNREAD);
+        assert_eq!(change.status, NotificationStatus::READ);
+    }
+}
@@ -1,11 +1,13 @@
-use std::time::Duration;
+use std::{collections::HashMap, sync::{Arc, atomic::{AtomicBool, Ordering}}, time::{Duration, Instant}};
 use aws_sdk_sqs::{Client as SQSClient, types::{Message, QueueAttributeName, MessageSystemAttributeName}};
 use async_trait::async_trait;
 use serde_json::Value;
-use tokio::time::sleep;
+use tokio::{sync::Notify, time::sleep};
+use tracing::Instrument;
 
 use crate::{
-    services::notification::NotificationService, adapters::memo_events::processors::event_type_processor as event_type_processor,
+    telemetry,
+    services::{notification::SharedNotificationService, health::Heartbeat, metrics::{self, MessageStage}}, adapters::memo_events::processors::event_type_processor as event_type_processor,
     adapters::memo_events::processors::{model::{CreateMessageProcessor, CreateMessageProcessorOption}, event_type::MemoEventTypes, event_type_processor::EventTypeProcessorInterface}
 };
 
@@ -13,9 +15,9 @@ use crate::{
 #[async_trait]
 pub trait SQSPollerInterface {
     async fn new(option: SQSPollerOption) -> Self;
-    async fn start_processing(&mut self);
+    async fn start_processing(&self);
     async fn stop_processing(&self);
-    async fn poll_once(&mut self);
+    async fn poll_once(&self);
     async fn delegate_event_to_processor(&self, message: Message);
 }
 
@@ -27,17 +29,25 @@ pub struct SQSPollerOption {
     pub wait_time_seconds: Option<i32>,
     pub max_number_of_messages: Option<i32>,
     pub max_retry: Option<i32>,
-    pub notification_service: NotificationService,
+    // pause between polls
+    pub interval_seconds: Option<u64>,
+    pub notification_service: SharedNotificationService,
+    // beaten after every poll, readiness reports the poller stuck when it goes stale
+    pub heartbeat: Option<Arc<Heartbeat>>,
 }
 
 pub struct SQSPoller {
-    processing: bool,
+    processing: AtomicBool,
+    // wakes the loop from its pause between polls when processing stops
+    stopped: Notify,
     sqs_client: SQSClient,
     failure_queue: String,
     sqs_queue: String,
     wait_time_seconds: i32,
     max_number_of_messages: i32,
     max_retry: i32,
+    interval_seconds: u64,
+    heartbeat: Arc<Heartbeat>,
     create_message_processor: CreateMessageProcessor,
 }
 
@@ -49,43 +59,58 @@ impl SQSPollerInterface for SQSPoller {
             notification_service: option.notification_service,
         });
         SQSPoller {
-            processing: false,
+            processing: AtomicBool::new(false),
+            stopped: Notify::new(),
             sqs_client: option.sqs_client,
             sqs_queue: option.sqs_queue,
             failure_queue: option.failure_queue,
             wait_time_seconds: option.wait_time_seconds.unwrap_or(10),
             max_retry: option.max_retry.unwrap_or(10),
             max_number_of_messages: option.max_number_of_messages.unwrap_or(10),
-            create_message_processor: create_message_processor,
+            interval_seconds: option.interval_seconds.unwrap_or(5),
+            heartbeat: option.heartbeat.unwrap_or_default(),
+            create_message_processor,
         }
     }
 
-    async fn start_processing(&mut self) {
-        self.processing = true;
+    async fn start_processing(&self) {
+        self.processing.store(true, Ordering::SeqCst);
         let mut i = 0;
-        while self.processing {
+        while self.processing.load(Ordering::SeqCst) {
             self.poll_once().await;
-            tracing::info!("Sleeping for 5 seconds... {:?}", i);
-            sleep(Duration::from_secs(5)).await;
+            self.heartbeat.beat();
+            tracing::info!("Sleeping for {} seconds... {:?}", self.interval_seconds, i);
+            tokio::select! {
+                _ = sleep(Duration::from_secs(self.interval_seconds)) => {},
+                _ = self.stopped.notified() => {},
+            }
             i += 1;
         }
+        tracing::info!("Stopped processing {}", self.sqs_queue);
     }
 
-    async fn poll_once(&mut self) {
+    async fn poll_once(&self) {
+        let receive_span = tracing::info_span!("sqs.receive", otel.kind = "consumer", messaging.destination = %self.sqs_queue, messaging.batch_size = tracing::field::Empty);
         let resp = self.sqs_client.receive_message()
             .queue_url(self.sqs_queue.clone())
             .max_number_of_messages(self.max_number_of_messages)
             .attribute_names(QueueAttributeName::All)
+            .message_attribute_names("All")
             .wait_time_seconds(self.wait_time_seconds)
             .send()
+            .instrument(receive_span.clone())
             .await
             .unwrap();
 
         match resp.messages {
             Some(messages) => {
                 tracing::info!("Received number of Message: {:?}", messages.len());
+                receive_span.record("messaging.batch_size", messages.len());
                 for message in messages {
-                    self.delegate_event_to_processor(message).await;
+                    // each message continues the trace of the producer that sent it
+                    let process_span = tracing::info_span!("sqs.process", otel.kind = "consumer", messaging.message_id = message.message_id.as_deref().unwrap_or_default(), event_type = tracing::field::Empty);
+                    telemetry::set_remote_parent(&process_span, &trace_carrier(&message));
+                    self.delegate_event_to_processor(message).instrument(process_span).await;
                     // let receipt_handle = message.receipt_handle.unwrap();
                     // let body = message.body.unwrap();
                     // let message_id = message.message_id.unwrap();
@@ -115,7 +140,17 @@ impl SQSPollerInterface for SQSPoller {
                     match detail_type {
                         "memo:message.created-1.0.0" => {
                             tracing::info!("EventProcessor::delegate_event_to_processor: event_type is CreateMessage");
+                            metrics::count_message(MessageStage::Received, detail_type);
+                            tracing::Span::current().record("event_type", detail_type);
+                            let started = Instant::now();
                             let result = self.create_message_processor.process(ref_body.to_owned()).await;
+                            let outcome = match &result {
+                                Ok(_) => "ok",
+                                Err(event_type_processor::ApplicationError::RetryableError(_)) => "retryable",
+                                Err(event_type_processor::ApplicationError::PermanentError(_)) => "permanent",
+                                Err(_) => "conflict",
+                            };
+                            metrics::observe_processor(detail_type, outcome, started.elapsed());
                             match result {
                                 Ok(_) => {
                                     self.sqs_client.delete_message()
@@ -125,14 +160,17 @@ impl SQSPollerInterface for SQSPoller {
                                         .await
                                         .unwrap();
                                     tracing::info!("Process successfully, deleted event from the queue");
+                                    metrics::count_message(MessageStage::Acked, detail_type);
                                 },
                                 Err(err) => {
                                     match err {
                                         event_type_processor::ApplicationError::RetryableError(retry_err) => {
                                             // tracing::info!(format!("event is retryable: {:?}", RetryableError));
                                             tracing::error!("event retryable error: {:?}", retry_err);
+                                            let mut dead_lettered = false;
                                             if let Some(reveived_count) = reveived_count {
                                                 if reveived_count > self.max_retry {
+                                                    dead_lettered = true;
                                                     // send event to DLQ
                                                     self.sqs_client.send_message()
                                                         .queue_url(self.failure_queue.clone())
@@ -148,6 +186,8 @@ impl SQSPollerInterface for SQSPoller {
                                                         .unwrap();
                                                 }
                                             }
+                                            let stage = if dead_lettered { MessageStage::DeadLettered } else { MessageStage::Retried };
+                                            metrics::count_message(stage, detail_type);
                                         },
                                         event_type_processor::ApplicationError::PermanentError(perm_err)=> {
                                             // tracing::info!(format!("event is permanent: {:?}", PermanentError));
@@ -165,6 +205,19 @@ impl SQSPollerInterface for SQSPoller {
                                                 .send()
                                                 .await
                                                 .unwrap();
+                                            metrics::count_message(MessageStage::DeadLettered, detail_type);
+                                        },
+                                        event_type_processor::ApplicationError::ConflictError(_) | event_type_processor::ApplicationError::NotFoundError(_)
+                                        | event_type_processor::ApplicationError::PreconditionFailedError(_) => {
+                                            // the event lost against the current item state, replaying it cannot succeed
+                                            tracing::error!("event conflict error: {:?}", err);
+                                            self.sqs_client.delete_message()
+                                                .queue_url(self.sqs_queue.clone())
+                                                .receipt_handle(message.receipt_handle.unwrap())
+                                                .send()
+                                                .await
+                                                .unwrap();
+                                            metrics::count_message(MessageStage::Acked, detail_type);
                                         },
                                     }
                                 },
@@ -182,7 +235,34 @@ impl SQSPollerInterface for SQSPoller {
         }
     }
 
+    // The batch being processed is finished first, so no received message is left half handled
     async fn stop_processing(&self) {
-        // Stop processing logic
+        self.processing.store(false, Ordering::SeqCst);
+        // notify_one keeps a permit when the loop is not waiting yet
+        self.stopped.notify_one();
+    }
+}
+
+// W3C trace context of a message, set by the producer as message attributes or, for events
+// routed through EventBridge, as `traceparent`/`tracestate` in the event detail
+fn trace_carrier(message: &Message) -> HashMap<String, String> {
+    let mut carrier = HashMap::new();
+    if let Some(attributes) = &message.message_attributes {
+        for name in ["traceparent", "tracestate"] {
+            if let Some(value) = attributes.get(name).and_then(|attribute| attribute.string_value()) {
+                carrier.insert(name.to_string(), value.to_string());
+            }
+        }
+    }
+    if carrier.contains_key("traceparent") {
+        return carrier;
     }
-}
\ No newline at end of file
+    if let Some(detail) = message.body.as_deref().and_then(|body| serde_json::from_str::<Value>(body).ok()).map(|json| json["detail"].clone()) {
+        for name in ["traceparent", "tracestate"] {
+            if let Some(value) = detail[name].as_str() {
+                carrier.insert(name.to_string(), value.to_string());
+            }
+        }
+    }
+    carrier
+}
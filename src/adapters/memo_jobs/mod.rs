@@ -0,0 +1,4 @@
+pub mod unread_count_reconciler;
+pub mod notification_archiver;
+pub mod outbox_relay;
+pub mod queue_depth_sampler;
\ No newline at end of file
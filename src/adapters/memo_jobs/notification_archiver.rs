@@ -0,0 +1,45 @@
+use std::time::Duration;
+use tokio::time::sleep;
+
+use crate::services::{
+    notification::SharedNotificationService,
+    archive::ArchiveSinkInterface,
+};
+
+pub struct NotificationArchiverOption {
+    pub notification_service: SharedNotificationService,
+    pub archive_sink: Box<dyn ArchiveSinkInterface + Send + Sync>,
+    pub interval_seconds: Option<u64>,
+    pub lead_seconds: Option<i64>,
+}
+
+// Exports notifications that expire within `lead_seconds` to the archive sink and marks them archived,
+// so they are archived before the DynamoDB TTL removes them once they expire
+pub struct NotificationArchiver {
+    notification_service: SharedNotificationService,
+    archive_sink: Box<dyn ArchiveSinkInterface + Send + Sync>,
+    interval_seconds: u64,
+    lead_seconds: i64,
+}
+
+impl NotificationArchiver {
+    pub fn new(option: NotificationArchiverOption) -> Self {
+        NotificationArchiver {
+            notification_service: option.notification_service,
+            archive_sink: option.archive_sink,
+            interval_seconds: option.interval_seconds.unwrap_or(3600),
+            lead_seconds: option.lead_seconds.unwrap_or(86400),
+        }
+    }
+
+    pub async fn start(&self) {
+        loop {
+            let horizon = chrono::Utc::now().timestamp() + self.lead_seconds;
+            match self.notification_service.archive_expiring_notifications(self.archive_sink.as_ref(), horizon).await {
+                Ok(archived) => tracing::info!("Archived {} expiring notifications", archived),
+                Err(e) => tracing::error!("archive_expiring_notifications Error: {:?}", e),
+            }
+            sleep(Duration::from_secs(self.interval_seconds)).await;
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use std::time::Duration;
+use tokio::time::sleep;
+
+use crate::{
+    client::eventbridge_client::EventPublisherInterface,
+    services::notification::SharedNotificationService,
+};
+
+pub struct OutboxRelayOption {
+    pub notification_service: SharedNotificationService,
+    pub event_publisher: Box<dyn EventPublisherInterface + Send + Sync>,
+    pub interval_millis: Option<u64>,
+}
+
+// Drains the outbox into the event publisher, pausing only when a pass found nothing to relay
+pub struct OutboxRelay {
+    notification_service: SharedNotificationService,
+    event_publisher: Box<dyn EventPublisherInterface + Send + Sync>,
+    interval_millis: u64,
+}
+
+impl OutboxRelay {
+    pub fn new(option: OutboxRelayOption) -> Self {
+        OutboxRelay {
+            notification_service: option.notification_service,
+            event_publisher: option.event_publisher,
+            interval_millis: option.interval_millis.unwrap_or(1000),
+        }
+    }
+
+    pub async fn start(&self) {
+        loop {
+            match self.notification_service.relay_outbox_events(self.event_publisher.as_ref()).await {
+                Ok(0) => sleep(Duration::from_millis(self.interval_millis)).await,
+                Ok(relayed) => tracing::info!("Relayed {} outbox events", relayed),
+                Err(e) => {
+                    tracing::error!("relay_outbox_events Error: {:?}", e);
+                    sleep(Duration::from_millis(self.interval_millis)).await;
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use std::time::Duration;
+use aws_sdk_sqs::{Client as SQSClient, Error as SqsError, types::QueueAttributeName};
+use tokio::time::sleep;
+
+use crate::services::metrics;
+
+pub struct QueueDepthSamplerOption {
+    pub sqs_client: SQSClient,
+    pub event_queue: String,
+    pub failure_queue: String,
+    pub interval_seconds: Option<u64>,
+}
+
+// Publishes how many messages wait in the event and failure queues, a growing failure queue
+// means events need a redrive
+pub struct QueueDepthSampler {
+    sqs_client: SQSClient,
+    event_queue: String,
+    failure_queue: String,
+    interval_seconds: u64,
+}
+
+impl QueueDepthSampler {
+    pub fn new(option: QueueDepthSamplerOption) -> Self {
+        QueueDepthSampler {
+            sqs_client: option.sqs_client,
+            event_queue: option.event_queue,
+            failure_queue: option.failure_queue,
+            interval_seconds: option.interval_seconds.unwrap_or(30),
+        }
+    }
+
+    pub async fn start(&self) {
+        loop {
+            for (queue, queue_url) in [("event", &self.event_queue), ("failure", &self.failure_queue)] {
+                match self.approximate_messages(queue_url).await {
+                    Ok(Some(messages)) => metrics::set_queue_depth(queue, messages),
+                    Ok(None) => tracing::error!("queue {} returned no ApproximateNumberOfMessages", queue_url),
+                    Err(e) => tracing::error!("get_queue_attributes on {} Error: {:?}", queue_url, e),
+                }
+            }
+            sleep(Duration::from_secs(self.interval_seconds)).await;
+        }
+    }
+
+    async fn approximate_messages(&self, queue_url: &str) -> Result<Option<f64>, SqsError> {
+        let result = self.sqs_client.get_queue_attributes()
+            .queue_url(queue_url)
+            .attribute_names(QueueAttributeName::ApproximateNumberOfMessages)
+            .send()
+            .await?;
+
+        Ok(result.attributes()
+            .and_then(|attributes| attributes.get(&QueueAttributeName::ApproximateNumberOfMessages))
+            .and_then(|messages| messages.parse::<f64>().ok()))
+    }
+}
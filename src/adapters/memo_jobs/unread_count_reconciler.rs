@@ -0,0 +1,35 @@
+use std::time::Duration;
+use tokio::time::sleep;
+
+use crate::services::notification::SharedNotificationService;
+
+pub struct UnreadCountReconcilerOption {
+    pub notification_service: SharedNotificationService,
+    pub interval_seconds: Option<u64>,
+}
+
+// Periodically recomputes the per-user unread counters from the notification items,
+// fixing counters that drifted (e.g. items written before the counter existed)
+pub struct UnreadCountReconciler {
+    notification_service: SharedNotificationService,
+    interval_seconds: u64,
+}
+
+impl UnreadCountReconciler {
+    pub fn new(option: UnreadCountReconcilerOption) -> Self {
+        UnreadCountReconciler {
+            notification_service: option.notification_service,
+            interval_seconds: option.interval_seconds.unwrap_or(3600),
+        }
+    }
+
+    pub async fn start(&self) {
+        loop {
+            match self.notification_service.reconcile_unread_counts().await {
+                Ok(reconciled) => tracing::info!("Reconciled {} unread counters", reconciled),
+                Err(e) => tracing::error!("reconcile_unread_counts Error: {:?}", e),
+            }
+            sleep(Duration::from_secs(self.interval_seconds)).await;
+        }
+    }
+}
@@ -0,0 +1,3 @@
+pub mod queue_redriver;
+pub mod test_event_sender;
+pub mod user_inspector;
\ No newline at end of file
@@ -0,0 +1,70 @@
+use aws_sdk_sqs::{Client as SQSClient, Error as SqsError};
+
+use crate::errors::main::SystemError;
+
+pub struct QueueRedriverOption {
+    pub sqs_client: SQSClient,
+    pub failure_queue: String,
+    pub event_queue: String,
+    pub max_messages: Option<usize>,
+}
+
+// Sends failed events back to the event queue once the cause is fixed. A message is only deleted
+// from the failure queue after it was sent, so a crash can duplicate an event but never lose one
+pub struct QueueRedriver {
+    sqs_client: SQSClient,
+    failure_queue: String,
+    event_queue: String,
+    max_messages: usize,
+}
+
+impl QueueRedriver {
+    pub fn new(option: QueueRedriverOption) -> Self {
+        QueueRedriver {
+            sqs_client: option.sqs_client,
+            failure_queue: option.failure_queue,
+            event_queue: option.event_queue,
+            max_messages: option.max_messages.unwrap_or(100),
+        }
+    }
+
+    // Returns how many messages were moved, stops early once the failure queue is empty
+    pub async fn redrive(&self) -> Result<usize, SystemError> {
+        let mut moved = 0;
+        while moved < self.max_messages {
+            let batch_size = (self.max_messages - moved).min(10) as i32;
+            let result = self.sqs_client.receive_message()
+                .queue_url(self.failure_queue.clone())
+                .max_number_of_messages(batch_size)
+                .wait_time_seconds(1)
+                .send()
+                .await
+                .map_err(SqsError::from)?;
+            let messages = result.messages.unwrap_or_default();
+            if messages.is_empty() {
+                break;
+            }
+            for message in messages {
+                let (body, receipt_handle) = match (message.body, message.receipt_handle) {
+                    (Some(body), Some(receipt_handle)) => (body, receipt_handle),
+                    _ => continue,
+                };
+                self.sqs_client.send_message()
+                    .queue_url(self.event_queue.clone())
+                    .message_body(body)
+                    .send()
+                    .await
+                    .map_err(SqsError::from)?;
+                self.sqs_client.delete_message()
+                    .queue_url(self.failure_queue.clone())
+                    .receipt_handle(receipt_handle)
+                    .send()
+                    .await
+                    .map_err(SqsError::from)?;
+                moved += 1;
+            }
+        }
+
+        Ok(moved)
+    }
+}
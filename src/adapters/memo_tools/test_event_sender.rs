@@ -0,0 +1,81 @@
+use aws_sdk_sqs::{Client as SQSClient, Error as SqsError, types::MessageAttributeValue};
+use chrono::Utc;
+use tracing::Instrument;
+use ulid::Ulid;
+
+use crate::{
+    adapters::memo_events::processors::model::{CreateMessageBody, CreateMessageDetail},
+    errors::main::{SerializationError, SystemError},
+    telemetry,
+};
+
+pub struct TestEventSenderOption {
+    pub sqs_client: SQSClient,
+    pub event_queue: String,
+}
+
+// Puts a message created event on the event queue in the same envelope EventBridge delivers,
+// to check a deployment end to end. The event starts a trace the READER continues
+pub struct TestEventSender {
+    sqs_client: SQSClient,
+    event_queue: String,
+}
+
+impl TestEventSender {
+    pub fn new(option: TestEventSenderOption) -> Self {
+        TestEventSender {
+            sqs_client: option.sqs_client,
+            event_queue: option.event_queue,
+        }
+    }
+
+    // Returns the notification id of the event sent
+    pub async fn send(&self, user_id: &str, topic_id: &str, content: &str) -> Result<String, SystemError> {
+        let notification_id = Ulid::new().to_string();
+        let now = Utc::now().to_rfc3339();
+        let body = CreateMessageBody {
+            version: "0".to_string(),
+            id: Ulid::new().to_string(),
+            detail_type: "memo:message.created".to_string(),
+            source: "memo-events-mgt.send-test-event".to_string(),
+            account: String::new(),
+            time: now.clone(),
+            region: String::new(),
+            resources: vec![],
+            detail: CreateMessageDetail {
+                notification_id: notification_id.clone(),
+                event_type: "memo:message.created-1.0.0".to_string(),
+                user_id: user_id.to_string(),
+                replyer_id: "memo-test".to_string(),
+                replyer_name: "Memo Test".to_string(),
+                replyer_avatar: String::new(),
+                topic_id: topic_id.to_string(),
+                message_id: Ulid::new().to_string(),
+                content: content.to_string(),
+                metadata: None,
+                recipient_ids: None,
+                created_time: now,
+            },
+        };
+        let body = serde_json::to_string(&body)
+            .map_err(|e| SerializationError::new(&format!("test event: {}", e)))?;
+        let span = tracing::info_span!("sqs.send", otel.kind = "producer", messaging.destination = %self.event_queue);
+        let mut request = self.sqs_client.send_message()
+            .queue_url(self.event_queue.clone())
+            .message_body(body);
+        for (name, value) in telemetry::trace_carrier(&span) {
+            let attribute = MessageAttributeValue::builder()
+                .data_type("String")
+                .string_value(value)
+                .build()
+                .map_err(|e| SerializationError::new(&format!("trace context attribute: {}", e)))?;
+            request = request.message_attributes(name, attribute);
+        }
+        request.send()
+            .instrument(span)
+            .await
+            .map_err(SqsError::from)?;
+
+        Ok(notification_id)
+    }
+}
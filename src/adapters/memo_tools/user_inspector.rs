@@ -0,0 +1,39 @@
+use serde_json::json;
+
+use crate::{
+    adapters::memo_events::processors::event_type_processor::ApplicationError,
+    services::{keys::{EntityKey, UserKey}, notification::SharedNotificationService},
+};
+
+pub struct UserInspectorOption {
+    pub notification_service: SharedNotificationService,
+    pub limit: Option<usize>,
+}
+
+// Reads what the API would show a user, for support questions without minting a token
+pub struct UserInspector {
+    notification_service: SharedNotificationService,
+    limit: usize,
+}
+
+impl UserInspector {
+    pub fn new(option: UserInspectorOption) -> Self {
+        UserInspector {
+            notification_service: option.notification_service,
+            limit: option.limit.unwrap_or(20),
+        }
+    }
+
+    pub async fn inspect(&self, user_key: UserKey) -> Result<serde_json::Value, ApplicationError> {
+        let user_id = user_key.id().to_string();
+        let unread_count = self.notification_service.get_unread_count(user_key.clone()).await?;
+        let mut notifications = self.notification_service.get_notification_by_user_id(user_key).await?;
+        notifications.truncate(self.limit);
+
+        Ok(json!({
+            "user_id": user_id,
+            "unread_count": unread_count,
+            "notifications": notifications,
+        }))
+    }
+}
@@ -1,2 +1,5 @@
 pub mod memo_events;
-pub mod memo_api;
\ No newline at end of file
+pub mod memo_api;
+pub mod memo_jobs;
+pub mod memo_bus;
+pub mod memo_tools;
\ No newline at end of file
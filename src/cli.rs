@@ -0,0 +1,170 @@
+use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand};
+
+// Command line flags, they take precedence over the environment and the config file.
+// Without a subcommand the module is picked by MEMO_MODULE as before
+#[derive(Debug, Parser)]
+#[command(name = "memo-events-mgt", about = "Memo events management")]
+pub struct Cli {
+    /// TOML file with settings, the environment and --set override it
+    #[arg(long, env = "MEMO_CONFIG", global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Override a setting, e.g. --set SERVER_PORT=3005, can be repeated
+    #[arg(long = "set", value_name = "NAME=VALUE", global = true)]
+    pub overrides: Vec<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the API server and the outbox relay (MEMO_MODULE=SERVER)
+    Serve(ServeArgs),
+    /// Poll the event queue and run the background jobs (MEMO_MODULE=READER)
+    Read(QueueArgs),
+    /// Run the reader and the server in one process (MEMO_MODULE=ALL)
+    All(AllArgs),
+    /// Create or update the table, then exit (MEMO_MODULE=MIGRATE)
+    Migrate(MigrateArgs),
+    /// Move messages from the failure queue back to the event queue
+    Redrive(RedriveArgs),
+    /// Send a message created event for a user to the event queue
+    SendTestEvent(SendTestEventArgs),
+    /// Print a user's unread count and latest notifications as JSON
+    InspectUser(InspectUserArgs),
+    /// Print the effective settings with secrets redacted, then validate them
+    PrintConfig,
+}
+
+#[derive(Debug, Args)]
+pub struct ServeArgs {
+    /// Port to listen on, SERVER_PORT
+    #[arg(long)]
+    pub port: Option<u16>,
+}
+
+#[derive(Debug, Args)]
+pub struct QueueArgs {
+    /// Queue URL to read events from, MEMO_SQS_EVENT_QUEUE
+    #[arg(long)]
+    pub event_queue: Option<String>,
+
+    /// Queue URL failed events go to, MEMO_FAILURE_QUEUE
+    #[arg(long)]
+    pub failure_queue: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct AllArgs {
+    #[command(flatten)]
+    pub serve: ServeArgs,
+
+    #[command(flatten)]
+    pub queues: QueueArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct MigrateArgs {
+    /// Switch the table to DYNAMODB_BILLING_MODE when it differs, MIGRATE_UPDATE_BILLING_MODE
+    #[arg(long)]
+    pub update_billing_mode: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct RedriveArgs {
+    #[command(flatten)]
+    pub queues: QueueArgs,
+
+    /// Stop after moving this many messages
+    #[arg(long, default_value_t = 100)]
+    pub max_messages: usize,
+}
+
+#[derive(Debug, Args)]
+pub struct SendTestEventArgs {
+    /// Recipient of the notification
+    #[arg(long)]
+    pub user_id: String,
+
+    #[arg(long, default_value = "test-topic")]
+    pub topic_id: String,
+
+    #[arg(long, default_value = "Test notification")]
+    pub content: String,
+
+    /// Queue URL to send to, MEMO_SQS_EVENT_QUEUE
+    #[arg(long)]
+    pub event_queue: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct InspectUserArgs {
+    pub user_id: String,
+
+    /// How many of the latest notifications to print
+    #[arg(long, default_value_t = 20)]
+    pub limit: usize,
+}
+
+impl Command {
+    // The module a subcommand stands for, it replaces MEMO_MODULE
+    pub fn module(&self) -> Option<&'static str> {
+        match self {
+            Command::Serve(_) => Some("SERVER"),
+            Command::Read(_) => Some("READER"),
+            Command::All(_) => Some("ALL"),
+            Command::Migrate(_) => Some("MIGRATE"),
+            Command::Redrive(_) => Some("REDRIVE"),
+            Command::SendTestEvent(_) => Some("SEND_TEST_EVENT"),
+            Command::InspectUser(_) => Some("INSPECT_USER"),
+            Command::PrintConfig => None,
+        }
+    }
+
+    // Settings given through the subcommand's flags, as `--set` assignments
+    pub fn overrides(&self) -> Vec<String> {
+        let mut overrides: Vec<String> = self.module().map(|module| format!("MEMO_MODULE={}", module)).into_iter().collect();
+        match self {
+            Command::Serve(serve) => serve.push_overrides(&mut overrides),
+            Command::Read(queues) => queues.push_overrides(&mut overrides),
+            Command::All(all) => {
+                all.serve.push_overrides(&mut overrides);
+                all.queues.push_overrides(&mut overrides);
+            },
+            Command::Redrive(redrive) => redrive.queues.push_overrides(&mut overrides),
+            Command::SendTestEvent(send) => {
+                if let Some(event_queue) = &send.event_queue {
+                    overrides.push(format!("MEMO_SQS_EVENT_QUEUE={}", event_queue));
+                }
+            },
+            Command::Migrate(migrate) => {
+                if migrate.update_billing_mode {
+                    overrides.push("MIGRATE_UPDATE_BILLING_MODE=true".to_string());
+                }
+            },
+            Command::InspectUser(_) | Command::PrintConfig => {},
+        }
+        overrides
+    }
+}
+
+impl ServeArgs {
+    fn push_overrides(&self, overrides: &mut Vec<String>) {
+        if let Some(port) = self.port {
+            overrides.push(format!("SERVER_PORT={}", port));
+        }
+    }
+}
+
+impl QueueArgs {
+    fn push_overrides(&self, overrides: &mut Vec<String>) {
+        if let Some(event_queue) = &self.event_queue {
+            overrides.push(format!("MEMO_SQS_EVENT_QUEUE={}", event_queue));
+        }
+        if let Some(failure_queue) = &self.failure_queue {
+            overrides.push(format!("MEMO_FAILURE_QUEUE={}", failure_queue));
+        }
+    }
+}
@@ -0,0 +1,34 @@
+// Per client overrides on top of the shared AWS config, used to point a single client at
+// DynamoDB Local or LocalStack without touching the others
+#[derive(Debug, Clone, Default)]
+pub struct ClientOption {
+    pub endpoint_url: Option<String>,
+    pub region: Option<String>,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+}
+
+impl ClientOption {
+    // Reads <PREFIX>_ENDPOINT_URL, <PREFIX>_REGION, <PREFIX>_ACCESS_KEY_ID and <PREFIX>_SECRET_ACCESS_KEY through `lookup`
+    pub fn from_lookup(prefix: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let read = |name: &str| lookup(&format!("{}_{}", prefix, name));
+        let option = ClientOption {
+            endpoint_url: read("ENDPOINT_URL"),
+            region: read("REGION"),
+            access_key_id: read("ACCESS_KEY_ID"),
+            secret_access_key: read("SECRET_ACCESS_KEY"),
+        };
+        if option.access_key_id.is_some() != option.secret_access_key.is_some() {
+            return Err(format!("{0}_ACCESS_KEY_ID and {0}_SECRET_ACCESS_KEY must be set together", prefix));
+        }
+
+        Ok(option)
+    }
+
+    pub fn static_credentials(&self) -> Option<(String, String)> {
+        match (&self.access_key_id, &self.secret_access_key) {
+            (Some(access_key_id), Some(secret_access_key)) => Some((access_key_id.clone(), secret_access_key.clone())),
+            _ => None,
+        }
+    }
+}
@@ -1,21 +1,22 @@
 use aws_config::SdkConfig;
-use aws_sdk_dynamodb::Client as DynamoDbClient;
-use lazy_static::lazy_static;
-use std::sync::Mutex;
+use aws_sdk_dynamodb::{
+    Client as DynamoDbClient,
+    config::{Builder, Credentials, Region},
+};
 
-// Define a global client within a Mutex for thread safety
-lazy_static! {
-    static ref DYNAMO_DB_CLIENT: Mutex<Option<DynamoDbClient>> = Mutex::new(None);
-}
-
-// Initialize the DynamoDB client
-pub async fn init(config: &SdkConfig) {
-    let client = DynamoDbClient::new(config);
-    let mut global_client = DYNAMO_DB_CLIENT.lock().unwrap();
-    *global_client = Some(client);
-}
+use crate::client::client_option::ClientOption;
 
-// Retrieve the DynamoDB client
-pub fn get() -> Option<DynamoDbClient> {
-    DYNAMO_DB_CLIENT.lock().unwrap().clone()
+// Build a DynamoDB client from the shared config with the given overrides applied
+pub fn build(config: &SdkConfig, option: &ClientOption) -> DynamoDbClient {
+    let mut builder = Builder::from(config);
+    if let Some(endpoint_url) = &option.endpoint_url {
+        builder = builder.endpoint_url(endpoint_url);
+    }
+    if let Some(region) = &option.region {
+        builder = builder.region(Region::new(region.clone()));
+    }
+    if let Some((access_key_id, secret_access_key)) = option.static_credentials() {
+        builder = builder.credentials_provider(Credentials::new(access_key_id, secret_access_key, None, None, "memo-static"));
+    }
+    DynamoDbClient::from_conf(builder.build())
 }
@@ -0,0 +1,22 @@
+use aws_config::SdkConfig;
+use aws_sdk_dynamodbstreams::{
+    Client as DynamoDbStreamsClient,
+    config::{Builder, Credentials, Region},
+};
+
+use crate::client::client_option::ClientOption;
+
+// Build a DynamoDB Streams client from the shared config with the given overrides applied
+pub fn build(config: &SdkConfig, option: &ClientOption) -> DynamoDbStreamsClient {
+    let mut builder = Builder::from(config);
+    if let Some(endpoint_url) = &option.endpoint_url {
+        builder = builder.endpoint_url(endpoint_url);
+    }
+    if let Some(region) = &option.region {
+        builder = builder.region(Region::new(region.clone()));
+    }
+    if let Some((access_key_id, secret_access_key)) = option.static_credentials() {
+        builder = builder.credentials_provider(Credentials::new(access_key_id, secret_access_key, None, None, "memo-static"));
+    }
+    DynamoDbStreamsClient::from_conf(builder.build())
+}
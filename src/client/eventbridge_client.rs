@@ -0,0 +1,298 @@
+use std::{sync::Mutex, time::Duration};
+use async_trait::async_trait;
+use aws_config::SdkConfig;
+use aws_sdk_eventbridge::{
+    Client as EventBridgeClient,
+    Error as EventBridgeError,
+    config::{Builder, Credentials, Region},
+    types::{PutEventsRequestEntry, PutEventsResultEntry},
+};
+use tokio::time::sleep;
+
+use crate::{
+    client::client_option::ClientOption,
+    errors::main::{PublishError, SerializationError, SystemError},
+};
+
+// PutEvents limits, an entry counts its source, detail type, detail and 14 bytes for the timestamp
+const PUT_EVENTS_MAX_ENTRIES: usize = 10;
+const PUT_EVENTS_MAX_BYTES: usize = 256 * 1024;
+const ENTRY_TIME_BYTES: usize = 14;
+const PUBLISH_MAX_ATTEMPTS: usize = 5;
+const IN_MEMORY_MAX_EVENTS: usize = 1000;
+// entry error codes that can succeed when sent again, any other code rejects the event for good
+const RETRYABLE_ERROR_CODES: [&str; 3] = ["ThrottlingException", "InternalFailure", "InternalException"];
+
+// Build an EventBridge client from the shared config with the given overrides applied
+pub fn build(config: &SdkConfig, option: &ClientOption) -> EventBridgeClient {
+    let mut builder = Builder::from(config);
+    if let Some(endpoint_url) = &option.endpoint_url {
+        builder = builder.endpoint_url(endpoint_url);
+    }
+    if let Some(region) = &option.region {
+        builder = builder.region(Region::new(region.clone()));
+    }
+    if let Some((access_key_id, secret_access_key)) = option.static_credentials() {
+        builder = builder.credentials_provider(Credentials::new(access_key_id, secret_access_key, None, None, "memo-static"));
+    }
+    EventBridgeClient::from_conf(builder.build())
+}
+
+// Where relayed outbox events go
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventPublisherMode {
+    EventBridge,
+    // logged and kept in memory, for local runs without an event bus
+    Memory,
+}
+
+impl EventPublisherMode {
+    pub fn parse(value: &str) -> Result<Self, SerializationError> {
+        match value {
+            "EVENTBRIDGE" => Ok(EventPublisherMode::EventBridge),
+            "MEMORY" => Ok(EventPublisherMode::Memory),
+            _ => Err(SerializationError::new(&format!("invalid event publisher {:?}, expected EVENTBRIDGE or MEMORY", value))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PublishEvent {
+    pub source: String,
+    pub detail_type: String,
+    // JSON document
+    pub detail: String,
+}
+
+impl PublishEvent {
+    fn size(&self) -> usize {
+        ENTRY_TIME_BYTES + self.source.len() + self.detail_type.len() + self.detail.len()
+    }
+}
+
+// An event the bus can never accept, by its position in the published list
+#[derive(Debug, Clone, PartialEq)]
+pub struct RejectedEvent {
+    pub index: usize,
+    pub reason: String,
+}
+
+#[async_trait]
+pub trait EventPublisherInterface {
+    // Succeeds once every event was accepted or rejected for good, the rejected ones are returned
+    async fn publish(&self, events: Vec<PublishEvent>) -> Result<Vec<RejectedEvent>, SystemError>;
+}
+
+// Groups events into requests within the entry count and payload size limits, events over the
+// payload limit on their own go into no request. Batched events keep their position in the list
+fn batches(events: Vec<PublishEvent>) -> (Vec<Vec<(usize, PublishEvent)>>, Vec<RejectedEvent>) {
+    let mut batches: Vec<Vec<(usize, PublishEvent)>> = vec![];
+    let mut rejected = vec![];
+    let mut batch_bytes = 0;
+    for (index, event) in events.into_iter().enumerate() {
+        let size = event.size();
+        if size > PUT_EVENTS_MAX_BYTES {
+            rejected.push(RejectedEvent {
+                index,
+                reason: format!("{} event of {} bytes is over the EventBridge limit of {}", event.detail_type, size, PUT_EVENTS_MAX_BYTES),
+            });
+            continue;
+        }
+        match batches.last_mut() {
+            Some(batch) if batch.len() < PUT_EVENTS_MAX_ENTRIES && batch_bytes + size <= PUT_EVENTS_MAX_BYTES => {
+                batch_bytes += size;
+                batch.push((index, event));
+            },
+            _ => {
+                batch_bytes = size;
+                batches.push(vec![(index, event)]);
+            },
+        }
+    }
+    (batches, rejected)
+}
+
+// Splits the events whose result entry carries an error into the ones to send again and the ones
+// rejected for good, result entries line up with the request
+fn failed_events(pending: Vec<(usize, PublishEvent)>, entries: &[PutEventsResultEntry], attempt: usize) -> (Vec<(usize, PublishEvent)>, Vec<RejectedEvent>) {
+    let mut retry = vec![];
+    let mut rejected = vec![];
+    for ((index, event), entry) in pending.into_iter().zip(entries.iter()) {
+        let Some(error_code) = entry.error_code() else { continue };
+        tracing::error!("EventBridge rejected {} event (attempt {}): {} {:?}", event.detail_type, attempt, error_code, entry.error_message());
+        if RETRYABLE_ERROR_CODES.contains(&error_code) {
+            retry.push((index, event));
+        } else {
+            rejected.push(RejectedEvent {
+                index,
+                reason: format!("{} event rejected by EventBridge: {} {}", event.detail_type, error_code, entry.error_message().unwrap_or_default()),
+            });
+        }
+    }
+    (retry, rejected)
+}
+
+#[derive(Debug, Clone)]
+pub struct EventBridgePublisher {
+    pub client: EventBridgeClient,
+    pub event_bus_name: String,
+}
+
+impl EventBridgePublisher {
+    async fn put_events(&self, mut pending: Vec<(usize, PublishEvent)>) -> Result<Vec<RejectedEvent>, SystemError> {
+        let mut rejected = vec![];
+        let mut backoff_millis = 100;
+        for attempt in 1..=PUBLISH_MAX_ATTEMPTS {
+            let entries = pending.iter().map(|(_, event)| {
+                PutEventsRequestEntry::builder()
+                    .event_bus_name(self.event_bus_name.clone())
+                    .source(event.source.clone())
+                    .detail_type(event.detail_type.clone())
+                    .detail(event.detail.clone())
+                    .build()
+            }).collect::<Vec<_>>();
+            let result = self.client.put_events()
+                .set_entries(Some(entries))
+                .send()
+                .await
+                .map_err(EventBridgeError::from)?;
+            if result.failed_entry_count == 0 {
+                return Ok(rejected);
+            }
+
+            // only the ones that failed for a passing reason are sent again
+            let (retry, permanent) = failed_events(pending, result.entries(), attempt);
+            rejected.extend(permanent);
+            pending = retry;
+            if pending.is_empty() {
+                return Ok(rejected);
+            }
+            sleep(Duration::from_millis(backoff_millis)).await;
+            backoff_millis *= 2;
+        }
+
+        Err(PublishError::new(&format!("{} events still rejected after {} attempts", pending.len(), PUBLISH_MAX_ATTEMPTS)).into())
+    }
+}
+
+#[async_trait]
+impl EventPublisherInterface for EventBridgePublisher {
+    async fn publish(&self, events: Vec<PublishEvent>) -> Result<Vec<RejectedEvent>, SystemError> {
+        let (batches, mut rejected) = batches(events);
+        for batch in batches {
+            rejected.extend(self.put_events(batch).await?);
+        }
+
+        Ok(rejected)
+    }
+}
+
+// Keeps the latest published events in memory and logs them, for local setups without an event bus and for tests
+#[derive(Debug, Default)]
+pub struct InMemoryEventPublisher {
+    published: Mutex<Vec<PublishEvent>>,
+}
+
+impl InMemoryEventPublisher {
+    #[cfg(test)]
+    pub fn published(&self) -> Vec<PublishEvent> {
+        self.published.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl EventPublisherInterface for InMemoryEventPublisher {
+    async fn publish(&self, events: Vec<PublishEvent>) -> Result<Vec<RejectedEvent>, SystemError> {
+        for event in events.iter() {
+            tracing::info!("published {} {}", event.detail_type, event.detail);
+        }
+        let mut published = self.published.lock().unwrap();
+        published.extend(events);
+        let overflow = published.len().saturating_sub(IN_MEMORY_MAX_EVENTS);
+        published.drain(..overflow);
+
+        Ok(vec![])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(detail_bytes: usize) -> PublishEvent {
+        PublishEvent {
+            source: "memo".to_string(),
+            detail_type: "Test".to_string(),
+            detail: "x".repeat(detail_bytes),
+        }
+    }
+
+    fn batch_sizes(events: Vec<PublishEvent>) -> Vec<usize> {
+        batches(events).0.iter().map(Vec::len).collect()
+    }
+
+    fn result_entry(error_code: Option<&str>) -> PutEventsResultEntry {
+        match error_code {
+            Some(error_code) => PutEventsResultEntry::builder().error_code(error_code).error_message("failed").build(),
+            None => PutEventsResultEntry::builder().event_id("accepted").build(),
+        }
+    }
+
+    #[test]
+    fn batches_hold_at_most_ten_entries() {
+        assert_eq!(batch_sizes((0..25).map(|_| event(10)).collect()), vec![10, 10, 5]);
+    }
+
+    #[test]
+    fn batches_stay_within_the_payload_limit() {
+        // three events fill a request, the fourth starts the next one
+        let detail_bytes = PUT_EVENTS_MAX_BYTES / 3 - ENTRY_TIME_BYTES - "memo".len() - "Test".len();
+        assert_eq!(batch_sizes((0..4).map(|_| event(detail_bytes)).collect()), vec![3, 1]);
+        assert_eq!(batch_sizes(vec![event(detail_bytes + 2), event(detail_bytes), event(detail_bytes)]), vec![2, 1]);
+    }
+
+    #[test]
+    fn batches_keep_the_event_order() {
+        let events: Vec<PublishEvent> = (0..12).map(event).collect();
+        let flattened: Vec<(usize, PublishEvent)> = batches(events.clone()).0.into_iter().flatten().collect();
+        assert_eq!(flattened, events.into_iter().enumerate().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn oversized_events_are_rejected_by_position() {
+        let (batches, rejected) = batches(vec![event(10), event(PUT_EVENTS_MAX_BYTES), event(20)]);
+        assert_eq!(batches, vec![vec![(0, event(10)), (2, event(20))]]);
+        assert_eq!(rejected.iter().map(|rejected| rejected.index).collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn only_failed_entries_are_sent_again() {
+        let pending: Vec<(usize, PublishEvent)> = (1..=4).map(|size| (size, event(size))).collect();
+        let entries = vec![result_entry(None), result_entry(Some("ThrottlingException")), result_entry(None), result_entry(Some("InternalFailure"))];
+        let (retry, rejected) = failed_events(pending, &entries, 1);
+        assert_eq!(retry, vec![(2, event(2)), (4, event(4))]);
+        assert!(rejected.is_empty());
+    }
+
+    #[test]
+    fn permanent_entry_errors_are_rejected_by_position() {
+        let pending: Vec<(usize, PublishEvent)> = (0..4).map(|index| (index + 10, event(index))).collect();
+        let entries = vec![result_entry(Some("MalformedDetail")), result_entry(Some("InternalException")), result_entry(None), result_entry(Some("AccessDeniedException"))];
+        let (retry, rejected) = failed_events(pending, &entries, 1);
+        assert_eq!(retry, vec![(11, event(1))]);
+        assert_eq!(rejected.iter().map(|rejected| rejected.index).collect::<Vec<_>>(), vec![10, 13]);
+        assert!(rejected[0].reason.contains("MalformedDetail"), "{}", rejected[0].reason);
+    }
+
+    #[tokio::test]
+    async fn in_memory_publisher_keeps_the_latest_events() {
+        let publisher = InMemoryEventPublisher::default();
+        publisher.publish((0..IN_MEMORY_MAX_EVENTS).map(|_| event(1)).collect()).await.unwrap();
+        let rejected = publisher.publish(vec![event(2)]).await.unwrap();
+
+        assert!(rejected.is_empty());
+        let published = publisher.published();
+        assert_eq!(published.len(), IN_MEMORY_MAX_EVENTS);
+        assert_eq!(published.last(), Some(&event(2)));
+    }
+}
@@ -1 +1,5 @@
-pub mod dynamodb_client;
\ No newline at end of file
+pub mod client_option;
+pub mod dynamodb_client;
+pub mod dynamodbstreams_client;
+pub mod sqs_client;
+pub mod eventbridge_client;
\ No newline at end of file
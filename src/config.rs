@@ -0,0 +1,579 @@
+use std::{collections::BTreeMap, env, fmt, fs, path::{Path, PathBuf}, str::FromStr};
+use aws_sdk_dynamodb::types::BillingMode;
+
+use crate::{
+    client::{client_option::ClientOption, eventbridge_client::EventPublisherMode},
+    errors::main::ConfigError,
+    services::{
+        bus::ChangeBusMode,
+        cache::NotificationCacheOption,
+        keys::{NotificationIdMode, USER_CREATED_TIME_INDEX},
+        migration::parse_billing_mode,
+        retention::RetentionPolicy,
+    },
+    supervisor::{Component, Components},
+};
+
+pub struct Setting {
+    pub name: &'static str,
+    pub default: Option<&'static str>,
+    // shown redacted by print-config
+    pub secret: bool,
+}
+
+const fn setting(name: &'static str, default: Option<&'static str>) -> Setting {
+    Setting { name, default, secret: false }
+}
+
+const fn secret(name: &'static str) -> Setting {
+    Setting { name, default: None, secret: true }
+}
+
+// Every setting the service reads. The names are the environment variables, a TOML file uses the
+// same names in lower case where tables join with `_`, e.g. `[server] port` is SERVER_PORT
+pub const SETTINGS: &[Setting] = &[
+    setting("MEMO_MODULE", None),
+    setting("MEMO_COMPONENTS", None),
+    setting("SHUTDOWN_TIMEOUT_SECONDS", Some("30")),
+    setting("DYNAMODB_TABLE_NAME", None),
+    setting("DYNAMODB_CREATED_TIME_INDEX", Some(USER_CREATED_TIME_INDEX)),
+    setting("DYNAMODB_BILLING_MODE", Some("PAY_PER_REQUEST")),
+    setting("MIGRATE_UPDATE_BILLING_MODE", Some("false")),
+    setting("DYNAMODB_ENDPOINT_URL", None),
+    setting("DYNAMODB_REGION", None),
+    setting("DYNAMODB_ACCESS_KEY_ID", None),
+    secret("DYNAMODB_SECRET_ACCESS_KEY"),
+    setting("SQS_ENDPOINT_URL", None),
+    setting("SQS_REGION", None),
+    setting("SQS_ACCESS_KEY_ID", None),
+    secret("SQS_SECRET_ACCESS_KEY"),
+    setting("EVENTBRIDGE_ENDPOINT_URL", None),
+    setting("EVENTBRIDGE_REGION", None),
+    setting("EVENTBRIDGE_ACCESS_KEY_ID", None),
+    secret("EVENTBRIDGE_SECRET_ACCESS_KEY"),
+    setting("NOTIFICATION_RETENTION_DAYS", None),
+    setting("NOTIFICATION_ID_MODE", Some("UPSTREAM")),
+    setting("NOTIFICATION_CACHE_CAPACITY", Some("0")),
+    setting("NOTIFICATION_CACHE_TTL_SECONDS", Some("30")),
+    setting("NOTIFICATION_ARCHIVE_DIR", None),
+    setting("NOTIFICATION_ARCHIVE_INTERVAL_SECONDS", Some("3600")),
+    setting("NOTIFICATION_ARCHIVE_LEAD_SECONDS", Some("86400")),
+    setting("UNREAD_RECONCILE_INTERVAL_SECONDS", Some("3600")),
+    setting("CHANGE_BUS", Some("LOCAL")),
+    setting("CHANGE_BUS_SOCKET_PATH", Some("/tmp/memo-change-bus.sock")),
+    setting("CHANGE_BUS_POLL_INTERVAL_MILLIS", Some("1000")),
+    setting("MEMO_SQS_EVENT_QUEUE", None),
+    setting("MEMO_FAILURE_QUEUE", None),
+    setting("POLLER_WAIT_TIME_SECONDS", Some("10")),
+    setting("POLLER_BATCH_SIZE", Some("10")),
+    setting("POLLER_MAX_RETRY", Some("5")),
+    setting("POLLER_INTERVAL_SECONDS", Some("5")),
+    setting("POLLER_HEARTBEAT_MAX_AGE_SECONDS", Some("120")),
+    setting("QUEUE_DEPTH_SAMPLE_INTERVAL_SECONDS", Some("30")),
+    setting("ADMIN_PORT", Some("8081")),
+    setting("SERVER_PORT", Some("3001")),
+    secret("JWT_SECRET"),
+    setting("SSE_HISTORY_SIZE", Some("100")),
+    setting("SSE_MAX_CONNECTIONS_PER_USER", Some("5")),
+    setting("EVENT_PUBLISHER", Some("MEMORY")),
+    setting("EVENT_BUS_NAME", Some("default")),
+    setting("OUTBOX_RELAY_INTERVAL_MILLIS", Some("1000")),
+    setting("OTEL_EXPORTER_OTLP_ENDPOINT", None),
+    setting("OTEL_SERVICE_NAME", Some("memo-events-mgt")),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SettingSource {
+    Default,
+    File,
+    Env,
+    Cli,
+}
+
+impl fmt::Display for SettingSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            SettingSource::Default => "default",
+            SettingSource::File => "file",
+            SettingSource::Env => "env",
+            SettingSource::Cli => "cli",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Raw setting values after layering defaults, the TOML file, the environment and `--set` flags, later layers win
+pub struct Settings {
+    values: BTreeMap<&'static str, (String, SettingSource)>,
+}
+
+impl Settings {
+    pub fn load(file: Option<&Path>, overrides: &[String]) -> Result<Self, ConfigError> {
+        let mut settings = Settings { values: BTreeMap::new() };
+        let mut errors = vec![];
+        for setting in SETTINGS {
+            if let Some(default) = setting.default {
+                settings.values.insert(setting.name, (default.to_string(), SettingSource::Default));
+            }
+        }
+        if let Some(file) = file {
+            settings.apply_file(file, &mut errors);
+        }
+        for setting in SETTINGS {
+            if let Some(value) = env::var(setting.name).ok().filter(|value| !value.is_empty()) {
+                settings.values.insert(setting.name, (value, SettingSource::Env));
+            }
+        }
+        for assignment in overrides {
+            match assignment.split_once('=') {
+                Some((name, value)) => settings.set(name.trim(), value.to_string(), SettingSource::Cli, &mut errors),
+                None => errors.push(format!("--set {:?} must look like NAME=VALUE", assignment)),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(settings)
+        } else {
+            Err(ConfigError::new(errors))
+        }
+    }
+
+    fn apply_file(&mut self, file: &Path, errors: &mut Vec<String>) {
+        let content = match fs::read_to_string(file) {
+            Ok(content) => content,
+            Err(e) => return errors.push(format!("cannot read config file {:?}: {}", file, e)),
+        };
+        let table = match content.parse::<toml::Table>() {
+            Ok(table) => table,
+            Err(e) => return errors.push(format!("invalid config file {:?}: {}", file, e)),
+        };
+        let mut values = vec![];
+        flatten_table("", &table, &mut values);
+        for (name, value) in values {
+            match value {
+                Some(value) => self.set(&name, value, SettingSource::File, errors),
+                None => errors.push(format!("{} in {:?} must be a string, number, boolean or list of them", name, file)),
+            }
+        }
+    }
+
+    fn set(&mut self, name: &str, value: String, source: SettingSource, errors: &mut Vec<String>) {
+        match SETTINGS.iter().find(|setting| setting.name == name) {
+            Some(setting) => {
+                self.values.insert(setting.name, (value, source));
+            },
+            None => errors.push(format!("unknown setting {} from {}", name, source)),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(|(value, _)| value.as_str())
+    }
+
+    // Every setting with its effective value and where it came from, secrets masked
+    pub fn redacted(&self) -> Vec<(&'static str, String, Option<SettingSource>)> {
+        SETTINGS.iter().map(|setting| match self.values.get(setting.name) {
+            Some((_, source)) if setting.secret => (setting.name, "********".to_string(), Some(*source)),
+            Some((value, source)) => (setting.name, value.clone(), Some(*source)),
+            None => (setting.name, "<unset>".to_string(), None),
+        }).collect()
+    }
+}
+
+fn flatten_table(prefix: &str, table: &toml::Table, values: &mut Vec<(String, Option<String>)>) {
+    for (key, value) in table {
+        let name = if prefix.is_empty() { key.to_uppercase() } else { format!("{}_{}", prefix, key.to_uppercase()) };
+        match value {
+            toml::Value::Table(table) => flatten_table(&name, table, values),
+            // lists become the comma separated form the environment uses, e.g. MEMO_COMPONENTS
+            toml::Value::Array(items) => {
+                let items: Option<Vec<String>> = items.iter().map(scalar_value).collect();
+                values.push((name, items.map(|items| items.join(","))));
+            },
+            value => values.push((name, scalar_value(value))),
+        }
+    }
+}
+
+fn scalar_value(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(value) => Some(value.clone()),
+        toml::Value::Integer(value) => Some(value.to_string()),
+        toml::Value::Float(value) => Some(value.to_string()),
+        toml::Value::Boolean(value) => Some(value.to_string()),
+        toml::Value::Datetime(value) => Some(value.to_string()),
+        toml::Value::Array(_) | toml::Value::Table(_) => None,
+    }
+}
+
+pub struct PollerConfig {
+    // both queues are required when the POLLER runs or for a redrive
+    pub event_queue: String,
+    pub failure_queue: String,
+    pub wait_time_seconds: i32,
+    pub batch_size: i32,
+    pub max_retry: i32,
+    pub interval_seconds: u64,
+    // readiness fails once the last poll is older than this
+    pub heartbeat_max_age_seconds: u64,
+    pub queue_depth_interval_seconds: u64,
+}
+
+pub struct ServerConfig {
+    pub port: u16,
+    // required when the SERVER runs
+    pub jwt_secret: String,
+    pub sse_history_size: usize,
+    pub sse_max_connections_per_user: usize,
+}
+
+pub struct OutboxConfig {
+    pub event_publisher: EventPublisherMode,
+    pub event_bus_name: String,
+    pub relay_interval_millis: u64,
+}
+
+pub struct MigrationConfig {
+    pub billing_mode: BillingMode,
+    // switch a table on another billing mode instead of only reporting it
+    pub update_billing_mode: bool,
+}
+
+pub struct TelemetryConfig {
+    // spans are only exported when set
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+}
+
+pub struct ArchiveConfig {
+    pub directory: PathBuf,
+    pub interval_seconds: u64,
+    pub lead_seconds: i64,
+}
+
+// Validated configuration, built once at startup
+pub struct AppConfig {
+    pub module: String,
+    pub components: Components,
+    pub shutdown_timeout_seconds: u64,
+    pub table_name: String,
+    pub created_time_index: String,
+    pub dynamodb: ClientOption,
+    pub sqs: ClientOption,
+    pub eventbridge: ClientOption,
+    pub retention_policy: RetentionPolicy,
+    pub notification_id_mode: NotificationIdMode,
+    // no cache when the capacity is 0
+    pub cache: Option<NotificationCacheOption>,
+    // no archival when no directory is set, expired notifications are then only removed by the TTL
+    pub archive: Option<ArchiveConfig>,
+    pub reconcile_interval_seconds: u64,
+    pub change_bus: ChangeBusMode,
+    pub change_bus_socket_path: PathBuf,
+    pub change_bus_poll_interval_millis: u64,
+    // health endpoints of a process without the SERVER
+    pub admin_port: u16,
+    pub poller: PollerConfig,
+    pub server: ServerConfig,
+    pub outbox: OutboxConfig,
+    pub migration: MigrationConfig,
+    pub telemetry: TelemetryConfig,
+}
+
+impl AppConfig {
+    pub fn from_settings(settings: &Settings) -> Result<Self, ConfigError> {
+        let mut reader = SettingsReader { settings, errors: vec![] };
+
+        let module = reader.required("MEMO_MODULE");
+        let components = match settings.get("MEMO_COMPONENTS") {
+            Some(_) => reader.parse("MEMO_COMPONENTS", Components::parse),
+            None => {
+                let components = Components::for_module(&module);
+                if components.is_none() && !module.is_empty() {
+                    reader.errors.push(format!("MEMO_MODULE: invalid module {:?}, expected one of {}", module, Components::module_names().join(", ")));
+                }
+                components
+            },
+        }.unwrap_or_default();
+        let poller_runs = components.contains(Component::Poller);
+        // redrive and send-test-event work on the queues without running the poller
+        let event_queue_used = poller_runs || module == "REDRIVE" || module == "SEND_TEST_EVENT";
+        let failure_queue_used = poller_runs || module == "REDRIVE";
+        let server_runs = components.contains(Component::Server);
+
+        let cache_capacity: usize = reader.number("NOTIFICATION_CACHE_CAPACITY");
+        let cache_ttl_seconds: u64 = reader.number("NOTIFICATION_CACHE_TTL_SECONDS");
+        let archive_interval_seconds: u64 = reader.number("NOTIFICATION_ARCHIVE_INTERVAL_SECONDS");
+        let archive_lead_seconds: i64 = reader.number("NOTIFICATION_ARCHIVE_LEAD_SECONDS");
+        let config = AppConfig {
+            shutdown_timeout_seconds: reader.number("SHUTDOWN_TIMEOUT_SECONDS"),
+            table_name: reader.required("DYNAMODB_TABLE_NAME"),
+            created_time_index: reader.required("DYNAMODB_CREATED_TIME_INDEX"),
+            dynamodb: reader.client("DYNAMODB"),
+            sqs: reader.client("SQS"),
+            eventbridge: reader.client("EVENTBRIDGE"),
+            retention_policy: reader.parse("NOTIFICATION_RETENTION_DAYS", RetentionPolicy::parse).unwrap_or_default(),
+            notification_id_mode: reader.parse("NOTIFICATION_ID_MODE", NotificationIdMode::parse).unwrap_or(NotificationIdMode::Upstream),
+            cache: Some(cache_capacity).filter(|capacity| *capacity > 0).map(|capacity| NotificationCacheOption {
+                capacity,
+                ttl_seconds: Some(cache_ttl_seconds),
+            }),
+            archive: settings.get("NOTIFICATION_ARCHIVE_DIR").map(|directory| ArchiveConfig {
+                directory: directory.into(),
+                interval_seconds: archive_interval_seconds,
+                lead_seconds: archive_lead_seconds,
+            }),
+            reconcile_interval_seconds: reader.number("UNREAD_RECONCILE_INTERVAL_SECONDS"),
+            change_bus: reader.parse("CHANGE_BUS", ChangeBusMode::parse).unwrap_or(ChangeBusMode::Local),
+            change_bus_socket_path: reader.required("CHANGE_BUS_SOCKET_PATH").into(),
+            change_bus_poll_interval_millis: reader.number("CHANGE_BUS_POLL_INTERVAL_MILLIS"),
+            admin_port: reader.number("ADMIN_PORT"),
+            poller: PollerConfig {
+                event_queue: reader.required_if(event_queue_used, "MEMO_SQS_EVENT_QUEUE"),
+                failure_queue: reader.required_if(failure_queue_used, "MEMO_FAILURE_QUEUE"),
+                wait_time_seconds: reader.number_in("POLLER_WAIT_TIME_SECONDS", 0, Some(20)),
+                batch_size: reader.number_in("POLLER_BATCH_SIZE", 1, Some(10)),
+                max_retry: reader.number("POLLER_MAX_RETRY"),
+                interval_seconds: reader.number("POLLER_INTERVAL_SECONDS"),
+                heartbeat_max_age_seconds: reader.number_in("POLLER_HEARTBEAT_MAX_AGE_SECONDS", 1, None),
+                queue_depth_interval_seconds: reader.number_in("QUEUE_DEPTH_SAMPLE_INTERVAL_SECONDS", 1, None),
+            },
+            server: ServerConfig {
+                port: reader.number("SERVER_PORT"),
+                jwt_secret: reader.required_if(server_runs, "JWT_SECRET"),
+                sse_history_size: reader.number_in("SSE_HISTORY_SIZE", 1, None),
+                sse_max_connections_per_user: reader.number_in("SSE_MAX_CONNECTIONS_PER_USER", 1, None),
+            },
+            outbox: OutboxConfig {
+                event_publisher: reader.parse("EVENT_PUBLISHER", EventPublisherMode::parse).unwrap_or(EventPublisherMode::Memory),
+                event_bus_name: reader.required("EVENT_BUS_NAME"),
+                relay_interval_millis: reader.number("OUTBOX_RELAY_INTERVAL_MILLIS"),
+            },
+            migration: MigrationConfig {
+                billing_mode: reader.parse("DYNAMODB_BILLING_MODE", parse_billing_mode).unwrap_or(BillingMode::PayPerRequest),
+                update_billing_mode: reader.flag("MIGRATE_UPDATE_BILLING_MODE"),
+            },
+            telemetry: TelemetryConfig {
+                otlp_endpoint: settings.get("OTEL_EXPORTER_OTLP_ENDPOINT").map(str::to_string),
+                service_name: reader.required("OTEL_SERVICE_NAME"),
+            },
+            module,
+            components,
+        };
+
+        if reader.errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError::new(reader.errors))
+        }
+    }
+}
+
+// Reads typed values out of the settings, noting every problem instead of stopping at the first
+struct SettingsReader<'a> {
+    settings: &'a Settings,
+    errors: Vec<String>,
+}
+
+impl SettingsReader<'_> {
+    fn required(&mut self, name: &str) -> String {
+        self.required_if(true, name)
+    }
+
+    fn required_if(&mut self, required: bool, name: &str) -> String {
+        match self.settings.get(name) {
+            Some(value) => value.to_string(),
+            None => {
+                if required {
+                    self.errors.push(format!("{} must be set", name));
+                }
+                String::new()
+            },
+        }
+    }
+
+    fn parse<T, E: fmt::Display>(&mut self, name: &str, parse: impl Fn(&str) -> Result<T, E>) -> Option<T> {
+        let value = self.settings.get(name)?;
+        match parse(value) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.errors.push(format!("{}: {}", name, e));
+                None
+            },
+        }
+    }
+
+    fn number<T: FromStr + Default>(&mut self, name: &str) -> T {
+        self.parse(name, |value| value.trim().parse::<T>().map_err(|_| format!("{:?} is not a valid number", value)))
+            .unwrap_or_default()
+    }
+
+    fn flag(&mut self, name: &str) -> bool {
+        self.parse(name, |value| value.trim().parse::<bool>().map_err(|_| format!("{:?} is not true or false", value)))
+            .unwrap_or_default()
+    }
+
+    fn number_in<T: FromStr + Default + PartialOrd + fmt::Display + Copy>(&mut self, name: &str, min: T, max: Option<T>) -> T {
+        let value = self.parse(name, |value| value.trim().parse::<T>().map_err(|_| format!("{:?} is not a valid number", value)));
+        match (value, max) {
+            (Some(value), Some(max)) if value < min || value > max => self.errors.push(format!("{}: {} is out of range {}..={}", name, value, min, max)),
+            (Some(value), None) if value < min => self.errors.push(format!("{}: {} must be at least {}", name, value, min)),
+            _ => {},
+        }
+        value.unwrap_or_default()
+    }
+
+    fn client(&mut self, prefix: &str) -> ClientOption {
+        let settings = self.settings;
+        ClientOption::from_lookup(prefix, |name| settings.get(name).map(str::to_string))
+            .unwrap_or_else(|e| {
+                self.errors.push(e);
+                ClientOption::default()
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Mutex, MutexGuard};
+
+    // Settings::load reads the process environment, tests that touch it take turns
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    fn env_lock() -> MutexGuard<'static, ()> {
+        // a failed test must not fail the others
+        ENV_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn config_file(name: &str, content: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("memo-config-{}-{}.toml", name, std::process::id()));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn overrides(assignments: &[&str]) -> Vec<String> {
+        assignments.iter().map(|assignment| assignment.to_string()).collect()
+    }
+
+    #[test]
+    fn later_layers_win() {
+        let _lock = env_lock();
+        let file = config_file("layers", "[server]\nport = 4000\n[sse]\nhistory_size = 50\nmax_connections_per_user = 7\n");
+        env::set_var("SSE_HISTORY_SIZE", "60");
+        let settings = Settings::load(Some(&file), &overrides(&["SERVER_PORT=5000"]));
+        env::remove_var("SSE_HISTORY_SIZE");
+        fs::remove_file(&file).unwrap();
+        let settings = settings.unwrap();
+
+        assert_eq!(settings.get("SHUTDOWN_TIMEOUT_SECONDS"), Some("30"));
+        assert_eq!(settings.get("SSE_MAX_CONNECTIONS_PER_USER"), Some("7"));
+        assert_eq!(settings.get("SSE_HISTORY_SIZE"), Some("60"));
+        assert_eq!(settings.get("SERVER_PORT"), Some("5000"));
+        let sources: BTreeMap<_, _> = settings.redacted().into_iter().map(|(name, _, source)| (name, source)).collect();
+        assert_eq!(sources["SHUTDOWN_TIMEOUT_SECONDS"], Some(SettingSource::Default));
+        assert_eq!(sources["SSE_MAX_CONNECTIONS_PER_USER"], Some(SettingSource::File));
+        assert_eq!(sources["SSE_HISTORY_SIZE"], Some(SettingSource::Env));
+        assert_eq!(sources["SERVER_PORT"], Some(SettingSource::Cli));
+    }
+
+    #[test]
+    fn secrets_are_redacted() {
+        let _lock = env_lock();
+        let settings = Settings::load(None, &overrides(&["JWT_SECRET=hunter2"])).unwrap();
+        let jwt_secret = settings.redacted().into_iter().find(|(name, _, _)| *name == "JWT_SECRET").unwrap();
+        assert_eq!(jwt_secret.1, "********");
+        assert_eq!(settings.get("JWT_SECRET"), Some("hunter2"));
+    }
+
+    #[test]
+    fn load_reports_every_problem() {
+        let _lock = env_lock();
+        let file = config_file("problems", "unknown_setting = 1\n[server]\nport = { nested = true }\n");
+        let error = Settings::load(Some(&file), &overrides(&["BOGUS=1", "SERVER_PORT"])).err().unwrap().to_string();
+        fs::remove_file(&file).unwrap();
+
+        assert!(error.contains("unknown setting UNKNOWN_SETTING from file"), "{}", error);
+        assert!(error.contains("unknown setting SERVER_PORT_NESTED from file"), "{}", error);
+        assert!(error.contains("unknown setting BOGUS from cli"), "{}", error);
+        assert!(error.contains("--set \"SERVER_PORT\" must look like NAME=VALUE"), "{}", error);
+    }
+
+    #[test]
+    fn from_settings_reports_every_problem() {
+        let _lock = env_lock();
+        let settings = Settings::load(None, &overrides(&["MEMO_MODULE=ALL", "POLLER_BATCH_SIZE=11", "SERVER_PORT=http", "NOTIFICATION_ID_MODE=uuid", "DYNAMODB_BILLING_MODE=ON_DEMAND", "MIGRATE_UPDATE_BILLING_MODE=yes"])).unwrap();
+        let error = AppConfig::from_settings(&settings).err().unwrap().to_string();
+
+        assert!(error.contains("DYNAMODB_TABLE_NAME must be set"), "{}", error);
+        assert!(error.contains("JWT_SECRET must be set"), "{}", error);
+        assert!(error.contains("MEMO_SQS_EVENT_QUEUE must be set"), "{}", error);
+        assert!(error.contains("POLLER_BATCH_SIZE: 11 is out of range 1..=10"), "{}", error);
+        assert!(error.contains("SERVER_PORT: \"http\" is not a valid number"), "{}", error);
+        assert!(error.contains("NOTIFICATION_ID_MODE: Serialization Error: invalid notification id mode \"uuid\""), "{}", error);
+        assert!(error.contains("DYNAMODB_BILLING_MODE: Serialization Error: invalid billing mode \"ON_DEMAND\""), "{}", error);
+        assert!(error.contains("MIGRATE_UPDATE_BILLING_MODE: \"yes\" is not true or false"), "{}", error);
+    }
+
+    #[test]
+    fn invalid_module_lists_every_module() {
+        let _lock = env_lock();
+        let settings = Settings::load(None, &overrides(&["MEMO_MODULE=WORKER", "DYNAMODB_TABLE_NAME=memo"])).unwrap();
+        let error = AppConfig::from_settings(&settings).err().unwrap().to_string();
+
+        assert!(error.contains("MEMO_MODULE: invalid module \"WORKER\", expected one of READER, SERVER, ALL, MIGRATE, REDRIVE, SEND_TEST_EVENT, INSPECT_USER"), "{}", error);
+    }
+
+    // the environment the compose `local` profile shares between its services
+    fn compose_local_env() -> Vec<(String, String)> {
+        let compose = fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("compose.yaml")).unwrap();
+        let mut lines = compose.lines().skip_while(|line| !line.contains("&local-env"));
+        let anchor = lines.next().expect("compose.yaml defines &local-env");
+        let indent = anchor.len() - anchor.trim_start().len();
+        lines
+            .take_while(|line| line.trim().is_empty() || line.len() - line.trim_start().len() > indent)
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.split_once(": "))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn compose_local_profile_is_valid() {
+        let _lock = env_lock();
+        let environment = compose_local_env();
+        assert!(!environment.is_empty());
+        // the containers get these as environment variables, the same way here
+        for (name, value) in &environment {
+            env::set_var(name, value);
+        }
+        let results: Vec<_> = ["MIGRATE", "READER", "SERVER"]
+            .iter()
+            .map(|module| {
+                let settings = Settings::load(None, &overrides(&[&format!("MEMO_MODULE={}", module)]));
+                (module, settings.and_then(|settings| AppConfig::from_settings(&settings).map(|_| ())))
+            })
+            .collect();
+        for (name, _) in &environment {
+            env::remove_var(name);
+        }
+
+        for (module, result) in results {
+            if let Err(error) = result {
+                panic!("compose local profile with MEMO_MODULE={}: {}", module, error);
+            }
+        }
+    }
+
+    #[test]
+    fn components_override_the_module() {
+        let _lock = env_lock();
+        let settings = Settings::load(None, &overrides(&["MEMO_MODULE=SERVER", "MEMO_COMPONENTS=OUTBOX_RELAY", "DYNAMODB_TABLE_NAME=memo"])).unwrap();
+        let config = AppConfig::from_settings(&settings).ok().unwrap();
+
+        assert_eq!(config.components.to_string(), "OUTBOX_RELAY");
+        // without the SERVER component the JWT secret is not needed
+        assert_eq!(config.server.jwt_secret, "");
+    }
+}
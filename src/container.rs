@@ -0,0 +1,100 @@
+use std::sync::Arc;
+use aws_config::SdkConfig;
+use aws_sdk_dynamodb::{Client as DynamoDbClient, types::BillingMode};
+use aws_sdk_dynamodbstreams::Client as DynamoDbStreamsClient;
+use aws_sdk_eventbridge::Client as EventBridgeClient;
+use aws_sdk_sqs::Client as SQSClient;
+
+use crate::{
+    client::{client_option::ClientOption, dynamodb_client, dynamodbstreams_client, sqs_client, eventbridge_client},
+    services::{
+        keys::NotificationIdMode,
+        migration::MigrationService,
+        bus::{ChangeBusMode, LocalNotificationBus, SharedNotificationBus},
+        cache::{CachedNotificationService, NotificationCacheOption},
+        notification::{NotificationService, SharedNotificationService},
+        retention::RetentionPolicy,
+        store::DatabaseStoreService,
+        measured_store::MeasuredDatabaseStore,
+    },
+};
+
+pub struct AppContainerOption {
+    pub table_name: String,
+    pub created_time_index: String,
+    pub retention_policy: RetentionPolicy,
+    pub notification_id_mode: NotificationIdMode,
+    pub dynamodb: ClientOption,
+    pub sqs: ClientOption,
+    pub eventbridge: ClientOption,
+    // no cache when unset
+    pub cache: Option<NotificationCacheOption>,
+    pub change_bus: ChangeBusMode,
+}
+
+// Everything the modules need, built once at startup and handed to READER, SERVER and MIGRATE
+#[derive(Clone)]
+pub struct AppContainer {
+    pub dynamodb_client: DynamoDbClient,
+    pub dynamodbstreams_client: DynamoDbStreamsClient,
+    pub sqs_client: SQSClient,
+    pub eventbridge_client: EventBridgeClient,
+    pub table_name: String,
+    pub created_time_index: String,
+    pub retention_policy: RetentionPolicy,
+    pub change_bus: ChangeBusMode,
+    // every change this process should react to, wherever it was written
+    pub notification_bus: SharedNotificationBus,
+    pub notification_service: SharedNotificationService,
+}
+
+impl AppContainer {
+    pub fn build(config: &SdkConfig, option: AppContainerOption) -> Self {
+        let dynamodb_client = dynamodb_client::build(config, &option.dynamodb);
+        let database_store_service = DatabaseStoreService {
+            store: dynamodb_client.clone(),
+            table_name: option.table_name.clone(),
+            notification_id_mode: option.notification_id_mode.clone(),
+            created_time_index: option.created_time_index.clone(),
+        };
+        let notification_bus: SharedNotificationBus = Arc::new(LocalNotificationBus::new());
+        // the stream also carries this process's own writes, publishing them locally as well would deliver them twice
+        let write_bus: SharedNotificationBus = match option.change_bus {
+            ChangeBusMode::DynamoDbStreams => Arc::new(LocalNotificationBus::new()),
+            ChangeBusMode::Local | ChangeBusMode::Uds => notification_bus.clone(),
+        };
+        let mut notification_service: SharedNotificationService = Arc::new(NotificationService {
+            database_store_service: Arc::new(MeasuredDatabaseStore::new(Arc::new(database_store_service))),
+            retention_policy: option.retention_policy.clone(),
+            notification_id_mode: option.notification_id_mode,
+            notification_bus: write_bus,
+        });
+        if let Some(cache) = option.cache {
+            notification_service = Arc::new(CachedNotificationService::new(notification_service, notification_bus.clone(), cache));
+        }
+
+        AppContainer {
+            dynamodb_client,
+            dynamodbstreams_client: dynamodbstreams_client::build(config, &option.dynamodb),
+            sqs_client: sqs_client::build(config, &option.sqs),
+            eventbridge_client: eventbridge_client::build(config, &option.eventbridge),
+            table_name: option.table_name,
+            created_time_index: option.created_time_index,
+            retention_policy: option.retention_policy,
+            change_bus: option.change_bus,
+            notification_bus,
+            notification_service,
+        }
+    }
+
+    pub fn migration_service(&self, billing_mode: BillingMode, update_billing_mode: bool) -> MigrationService {
+        MigrationService {
+            store: self.dynamodb_client.clone(),
+            table_name: self.table_name.clone(),
+            created_time_index: self.created_time_index.clone(),
+            retention_policy: self.retention_policy.clone(),
+            billing_mode,
+            update_billing_mode,
+        }
+    }
+}
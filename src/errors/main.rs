@@ -1,7 +1,9 @@
 use std::fmt;
 
 use aws_sdk_dynamodb::Error as DynamoDbError;
+use aws_sdk_dynamodbstreams::Error as DynamoDbStreamsError;
 use aws_sdk_eventbridge::Error as EventBridgeError;
+use aws_sdk_sqs::Error as SqsError;
 
 #[derive(Debug)]
 pub struct SerializationError {
@@ -24,27 +26,138 @@ impl fmt::Display for SerializationError {
 
 impl std::error::Error for SerializationError {}
 
+#[derive(Debug)]
+pub struct PublishError {
+    message: String,
+}
+
+impl PublishError {
+    pub fn new(message: &str) -> Self {
+        PublishError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for PublishError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Publish Error: {}", self.message)
+    }
+}
+
+impl std::error::Error for PublishError {}
+
+#[derive(Debug)]
+pub struct MigrationError {
+    message: String,
+}
+
+impl MigrationError {
+    pub fn new(message: &str) -> Self {
+        MigrationError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Migration Error: {}", self.message)
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+// Every problem found in the configuration, reported together instead of one per restart
+#[derive(Debug)]
+pub struct ConfigError {
+    errors: Vec<String>,
+}
+
+impl ConfigError {
+    pub fn new(errors: Vec<String>) -> Self {
+        ConfigError { errors }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Config Error:")?;
+        for error in self.errors.iter() {
+            write!(f, "\n  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum SystemError {
     DynamoDbError(DynamoDbError),
+    DynamoDbStreamsError(DynamoDbStreamsError),
     SerializationError(SerializationError),
     EventBridgeError(EventBridgeError),
+    SqsError(SqsError),
+    PublishError(PublishError),
+    MigrationError(MigrationError),
+}
+
+impl fmt::Display for SystemError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SystemError::DynamoDbError(e) => write!(f, "DynamoDB Error: {}", e),
+            SystemError::DynamoDbStreamsError(e) => write!(f, "DynamoDB Streams Error: {}", e),
+            SystemError::SerializationError(e) => write!(f, "{}", e),
+            SystemError::EventBridgeError(e) => write!(f, "EventBridge Error: {}", e),
+            SystemError::SqsError(e) => write!(f, "SQS Error: {}", e),
+            SystemError::PublishError(e) => write!(f, "{}", e),
+            SystemError::MigrationError(e) => write!(f, "{}", e),
+        }
+    }
 }
 
+impl std::error::Error for SystemError {}
+
 impl From<DynamoDbError> for SystemError {
     fn from(error: DynamoDbError) -> Self {
         SystemError::DynamoDbError(error)
     }
 }
 
+impl From<DynamoDbStreamsError> for SystemError {
+    fn from(error: DynamoDbStreamsError) -> Self {
+        SystemError::DynamoDbStreamsError(error)
+    }
+}
+
 impl From<EventBridgeError> for SystemError {
     fn from(error: EventBridgeError) -> Self {
         SystemError::EventBridgeError(error)
     }
 }
 
+impl From<SqsError> for SystemError {
+    fn from(error: SqsError) -> Self {
+        SystemError::SqsError(error)
+    }
+}
+
 impl From<SerializationError> for SystemError {
     fn from(error: SerializationError) -> Self {
         SystemError::SerializationError(error)
     }
-}
\ No newline at end of file
+}
+
+impl From<PublishError> for SystemError {
+    fn from(error: PublishError) -> Self {
+        SystemError::PublishError(error)
+    }
+}
+
+impl From<MigrationError> for SystemError {
+    fn from(error: MigrationError) -> Self {
+        SystemError::MigrationError(error)
+    }
+}
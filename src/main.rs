@@ -1,138 +1,363 @@
-use std::env;
-use aws_sdk_dynamodb::Client as DynamoDbClient;
-use serde::{Deserialize, Serialize};
-use aws_sdk_sqs::Client as SQSClient;
 use std::sync::Arc;
+use clap::Parser;
 use dotenv::dotenv;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 mod adapters;
+mod cli;
 mod client;
+mod config;
 mod errors;
 mod utils;
 mod services;
+mod container;
+mod supervisor;
+mod telemetry;
 
-use services::notification::NotificationService;
-use services::store::DatabaseStoreService;
+use services::bus::ChangeBusMode;
+use services::archive::JsonlArchiveSink;
+use services::health::{Heartbeat, HealthService, HealthServiceOption};
+use services::hub::{NotificationHub, NotificationHubOption};
+use services::keys::{EntityKey, UserKey};
 use adapters::memo_events::sqs_poller::{SQSPoller, SQSPollerOption, SQSPollerInterface};
-use adapters::memo_api::router;
-use client::dynamodb_client;
+use adapters::memo_api::{health, metrics, router};
+use adapters::memo_jobs::unread_count_reconciler::{UnreadCountReconciler, UnreadCountReconcilerOption};
+use adapters::memo_jobs::notification_archiver::{NotificationArchiver, NotificationArchiverOption};
+use adapters::memo_jobs::outbox_relay::{OutboxRelay, OutboxRelayOption};
+use adapters::memo_jobs::queue_depth_sampler::{QueueDepthSampler, QueueDepthSamplerOption};
+use adapters::memo_bus::uds_bridge::{UdsBusForwarder, UdsBusForwarderOption, UdsBusListener, UdsBusListenerOption};
+use adapters::memo_bus::dynamodb_stream_consumer::{DynamoDbStreamConsumer, DynamoDbStreamConsumerOption};
+use adapters::memo_tools::queue_redriver::{QueueRedriver, QueueRedriverOption};
+use adapters::memo_tools::test_event_sender::{TestEventSender, TestEventSenderOption};
+use adapters::memo_tools::user_inspector::{UserInspector, UserInspectorOption};
+use cli::{Cli, Command};
+use client::eventbridge_client::{EventPublisherInterface, EventPublisherMode, EventBridgePublisher, InMemoryEventPublisher};
+use config::{AppConfig, Settings};
+use container::{AppContainer, AppContainerOption};
+use supervisor::{Component, Supervisor, SupervisorOption};
+use telemetry::{Telemetry, TelemetryOption};
 
 
-#[derive(Debug, Serialize, Deserialize)]
-struct SQSEvent {
-    detail: EventDetail,
-}
-#[derive(Debug, Serialize, Deserialize)]
-struct EventDetail {
-    version: String,
-    id: String,
-    #[serde(rename = "type")]
-    event_type: String,
-    source: String,
-    time: String,
-    data: EventData,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct EventData {
-    notification_id: String,
-    #[serde(rename = "type")]
-    event_type: String,
-    user_id: String,
-    replyer_id: String,
-    replyer_name: String,
-    replyer_avatar: String,
-    topic_id: String,
-    message_id: String,
-    content: String,
-    created_time: String,
-}
-
-fn build_notification_service(db_client: DynamoDbClient, table_name: String) -> NotificationService {
-    let db_service = DatabaseStoreService {
-        store: db_client,
-        table_name: table_name,
-    };
-    let notification_service = NotificationService {
-        database_store_service: db_service,
-    };
-    return notification_service;
-}
-
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "memo-events-mgt=info".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-
     dotenv().ok();
-    let config = aws_config::from_env().load().await;
-    let memo_module = match env::var("MEMO_MODULE") {
-        Ok(value) => value,
+    let cli = Cli::parse();
+    // explicit --set flags win over the subcommand's own flags
+    let mut overrides = cli.command.as_ref().map(Command::overrides).unwrap_or_default();
+    overrides.extend(cli.overrides.iter().cloned());
+    let settings = match Settings::load(cli.config.as_deref(), &overrides) {
+        Ok(settings) => settings,
         Err(e) => {
-            eprintln!("Failed to get MEMO_MODULE from environment: {:?}", e);
-            return;
+            eprintln!("{}", e);
+            std::process::exit(1);
         }
     };
-    let memo_failure_queue = match env::var("MEMO_FAILURE_QUEUE") {
-        Ok(value) => value,
-        Err(e) => {
-            eprintln!("Failed to get MEMO_FAILURE_QUEUE from environment: {:?}", e);
-            return;
+    let app_config = AppConfig::from_settings(&settings);
+    if let Some(Command::PrintConfig) = &cli.command {
+        for (name, value, source) in settings.redacted() {
+            match source {
+                Some(source) => println!("{}={} ({})", name, value, source),
+                None => println!("{}={}", name, value),
+            }
         }
-    };
-    let memo_sqs_event_queue = match env::var("MEMO_SQS_EVENT_QUEUE") {
-        Ok(value) => value,
+        if let Err(e) = app_config {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let app_config = match app_config {
+        Ok(app_config) => app_config,
         Err(e) => {
-            eprintln!("Failed to get MEMO_SQS_EVENT_QUEUE from environment: {:?}", e);
-            return;
+            eprintln!("{}", e);
+            std::process::exit(1);
         }
     };
-    let dynamo_db_table_name = match env::var("DYNAMODB_TABLE_NAME") {
-        Ok(value) => value,
+
+    let telemetry = Telemetry::init(TelemetryOption {
+        otlp_endpoint: app_config.telemetry.otlp_endpoint.clone(),
+        service_name: app_config.telemetry.service_name.clone(),
+    });
+
+    let config = aws_config::from_env().load().await;
+    let container = AppContainer::build(&config, AppContainerOption {
+        table_name: app_config.table_name.clone(),
+        created_time_index: app_config.created_time_index.clone(),
+        retention_policy: app_config.retention_policy.clone(),
+        notification_id_mode: app_config.notification_id_mode.clone(),
+        dynamodb: app_config.dynamodb.clone(),
+        sqs: app_config.sqs.clone(),
+        eventbridge: app_config.eventbridge.clone(),
+        cache: app_config.cache.clone(),
+        change_bus: app_config.change_bus.clone(),
+    });
+    // one-shot modules run to completion and exit
+    let finished = match (app_config.module.as_str(), &cli.command) {
+        ("MIGRATE", _) => {
+            tracing::info!("Memo migrate module is running");
+            match container.migration_service(app_config.migration.billing_mode.clone(), app_config.migration.update_billing_mode).migrate().await {
+                Ok(_) => {
+                    tracing::info!("Migration finished");
+                    Some(true)
+                },
+                Err(e) => {
+                    eprintln!("Migration failed: {:?}", e);
+                    Some(false)
+                }
+            }
+        },
+        ("REDRIVE", command) => {
+            let max_messages = match command {
+                Some(Command::Redrive(redrive)) => Some(redrive.max_messages),
+                _ => None,
+            };
+            let redriver = QueueRedriver::new(QueueRedriverOption {
+                sqs_client: container.sqs_client.clone(),
+                failure_queue: app_config.poller.failure_queue.clone(),
+                event_queue: app_config.poller.event_queue.clone(),
+                max_messages,
+            });
+            match redriver.redrive().await {
+                Ok(moved) => {
+                    println!("Moved {} messages from {} to {}", moved, app_config.poller.failure_queue, app_config.poller.event_queue);
+                    Some(true)
+                },
+                Err(e) => {
+                    eprintln!("Redrive failed: {}", e);
+                    Some(false)
+                }
+            }
+        },
+        ("SEND_TEST_EVENT", Some(Command::SendTestEvent(send))) => {
+            let sender = TestEventSender::new(TestEventSenderOption {
+                sqs_client: container.sqs_client.clone(),
+                event_queue: app_config.poller.event_queue.clone(),
+            });
+            match sender.send(&send.user_id, &send.topic_id, &send.content).await {
+                Ok(notification_id) => {
+                    println!("Sent notification {} for user {}", notification_id, send.user_id);
+                    Some(true)
+                },
+                Err(e) => {
+                    eprintln!("Sending the test event failed: {}", e);
+                    Some(false)
+                }
+            }
+        },
+        ("INSPECT_USER", Some(Command::InspectUser(inspect))) => {
+            let inspector = UserInspector::new(UserInspectorOption {
+                notification_service: container.notification_service.clone(),
+                limit: Some(inspect.limit),
+            });
+            match UserKey::new(inspect.user_id.clone()) {
+                Ok(user_key) => match inspector.inspect(user_key).await {
+                    Ok(report) => {
+                        println!("{}", serde_json::to_string_pretty(&report).unwrap_or_default());
+                        Some(true)
+                    },
+                    Err(e) => {
+                        eprintln!("Inspecting user {} failed: {:?}", inspect.user_id, e);
+                        Some(false)
+                    }
+                },
+                Err(e) => {
+                    eprintln!("{}", e);
+                    Some(false)
+                }
+            }
+        },
+        ("SEND_TEST_EVENT", _) | ("INSPECT_USER", _) => {
+            eprintln!("MEMO_MODULE={} needs its arguments, run the {} subcommand instead", app_config.module, app_config.module.to_lowercase().replace('_', "-"));
+            Some(false)
+        },
+        _ => None,
+    };
+    if let Some(succeeded) = finished {
+        telemetry.shutdown();
+        if !succeeded {
+            std::process::exit(1);
+        }
+        return;
+    }
+    let components = &app_config.components;
+    tracing::info!("Memo {} module is running {}", app_config.module.to_lowercase(), components);
+    let mut supervisor = Supervisor::new(SupervisorOption {
+        shutdown_timeout_seconds: Some(app_config.shutdown_timeout_seconds),
+    });
+    let metrics_handle = match services::metrics::install() {
+        Ok(metrics_handle) => metrics_handle,
         Err(e) => {
-            eprintln!("Failed to get DYNAMODB_TABLE_NAME from environment: {:?}", e);
-            return;
+            eprintln!("Failed to install the metrics recorder: {:?}", e);
+            std::process::exit(1);
         }
     };
-    if memo_module.eq(&"READER".to_string()) {
-        tracing::info!("Memo reader module is running");
-        dynamodb_client::init(&config).await;
-        let dynamodb_client = dynamodb_client::get().unwrap();
-        let notification_service = build_notification_service(dynamodb_client, dynamo_db_table_name);
-        let sqs_client = SQSClient::new(&config);
-        let sqs_option =  SQSPollerOption {
-            sqs_client: sqs_client,
-            sqs_queue: memo_sqs_event_queue,
-            failure_queue: memo_failure_queue,
-            wait_time_seconds: Some(10),
-            max_number_of_messages: Some(10), // max is 10
-            max_retry: Some(5),
-            notification_service: notification_service,
+    let upkeep_handle = metrics_handle.clone();
+    supervisor.spawn("metrics upkeep", async move {
+        loop {
+            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+            upkeep_handle.run_upkeep();
+        }
+    });
+    // readiness covers the queue and the poll loop only where the poller runs
+    let poller_heartbeat = components.contains(Component::Poller).then(|| Arc::new(Heartbeat::default()));
+    let health_service = Arc::new(HealthService::new(HealthServiceOption {
+        dynamodb_client: container.dynamodb_client.clone(),
+        table_name: container.table_name.clone(),
+        sqs_client: poller_heartbeat.as_ref().map(|_| container.sqs_client.clone()),
+        queue_url: poller_heartbeat.as_ref().map(|_| app_config.poller.event_queue.clone()),
+        poller_heartbeat: poller_heartbeat.clone(),
+        heartbeat_max_age_seconds: Some(app_config.poller.heartbeat_max_age_seconds),
+    }));
+
+    if components.contains(Component::Reconciler) {
+        let reconciler = UnreadCountReconciler::new(UnreadCountReconcilerOption {
+            notification_service: container.notification_service.clone(),
+            interval_seconds: Some(app_config.reconcile_interval_seconds),
+        });
+        supervisor.spawn("reconciler", async move { reconciler.start().await });
+    }
+    // archival is optional, without a directory expired notifications are only removed by the TTL
+    if components.contains(Component::Archiver) {
+        if let Some(archive) = &app_config.archive {
+            let archiver = NotificationArchiver::new(NotificationArchiverOption {
+                notification_service: container.notification_service.clone(),
+                archive_sink: Box::new(JsonlArchiveSink { directory: archive.directory.clone() }),
+                interval_seconds: Some(archive.interval_seconds),
+                lead_seconds: Some(archive.lead_seconds),
+            });
+            supervisor.spawn("archiver", async move { archiver.start().await });
+        }
+    }
+
+    // in one process the local bus already connects writers and the server, the socket only bridges two processes
+    let bridge_over_socket = container.change_bus == ChangeBusMode::Uds
+        && components.contains(Component::Poller) != components.contains(Component::Server);
+    if components.contains(Component::Poller) {
+        // with a stream the SERVER sees these writes on its own, over a socket they have to be sent
+        if bridge_over_socket {
+            let forwarder = UdsBusForwarder::new(UdsBusForwarderOption {
+                notification_bus: container.notification_bus.clone(),
+                socket_path: app_config.change_bus_socket_path.clone(),
+            });
+            supervisor.spawn("change bus forwarder", async move { forwarder.start().await });
+        }
+        let poller_option =  SQSPollerOption {
+            sqs_client: container.sqs_client.clone(),
+            sqs_queue: app_config.poller.event_queue.clone(),
+            failure_queue: app_config.poller.failure_queue.clone(),
+            wait_time_seconds: Some(app_config.poller.wait_time_seconds),
+            max_number_of_messages: Some(app_config.poller.batch_size), // max is 10
+            max_retry: Some(app_config.poller.max_retry),
+            interval_seconds: Some(app_config.poller.interval_seconds),
+            notification_service: container.notification_service.clone(),
+            heartbeat: poller_heartbeat.clone(),
         };
-        SQSPoller::new(sqs_option).await.start_processing().await;
-        return;
-    } else if memo_module.eq(&"SERVER".to_string()) {
-        tracing::info!("Memo server module is running");
-        dynamodb_client::init(&config).await;
-        let dynamodb_client = dynamodb_client::get().unwrap();
+        let sampler = QueueDepthSampler::new(QueueDepthSamplerOption {
+            sqs_client: container.sqs_client.clone(),
+            event_queue: app_config.poller.event_queue.clone(),
+            failure_queue: app_config.poller.failure_queue.clone(),
+            interval_seconds: Some(app_config.poller.queue_depth_interval_seconds),
+        });
+        supervisor.spawn("queue depth sampler", async move { sampler.start().await });
+        let poller = SQSPoller::new(poller_option).await;
+        let shutdown = supervisor.shutdown_signal();
+        supervisor.spawn_graceful("poller", async move {
+            let processing = poller.start_processing();
+            tokio::pin!(processing);
+            tokio::select! {
+                _ = &mut processing => {},
+                _ = shutdown => {
+                    poller.stop_processing().await;
+                    processing.await;
+                },
+            }
+        });
+    }
+
+    if components.contains(Component::OutboxRelay) {
+        let event_publisher: Box<dyn EventPublisherInterface + Send + Sync> = match app_config.outbox.event_publisher {
+            EventPublisherMode::EventBridge => Box::new(EventBridgePublisher {
+                client: container.eventbridge_client.clone(),
+                event_bus_name: app_config.outbox.event_bus_name.clone(),
+            }),
+            EventPublisherMode::Memory => Box::new(InMemoryEventPublisher::default()),
+        };
+        let relay = OutboxRelay::new(OutboxRelayOption {
+            notification_service: container.notification_service.clone(),
+            event_publisher,
+            interval_millis: Some(app_config.outbox.relay_interval_millis),
+        });
+        supervisor.spawn("outbox relay", async move { relay.start().await });
+    }
+
+    if components.contains(Component::Server) {
+        match container.change_bus {
+            ChangeBusMode::Uds if bridge_over_socket => {
+                let listener = UdsBusListener::new(UdsBusListenerOption {
+                    notification_bus: container.notification_bus.clone(),
+                    socket_path: app_config.change_bus_socket_path.clone(),
+                });
+                supervisor.spawn("change bus listener", async move {
+                    if let Err(e) = listener.start().await {
+                        tracing::error!("change bus listener Error: {:?}", e);
+                    }
+                });
+            },
+            ChangeBusMode::DynamoDbStreams => {
+                let consumer = DynamoDbStreamConsumer::new(DynamoDbStreamConsumerOption {
+                    dynamodb_client: container.dynamodb_client.clone(),
+                    streams_client: container.dynamodbstreams_client.clone(),
+                    table_name: container.table_name.clone(),
+                    notification_bus: container.notification_bus.clone(),
+                    interval_millis: Some(app_config.change_bus_poll_interval_millis),
+                });
+                supervisor.spawn("dynamodb stream consumer", async move { consumer.start().await });
+            },
+            _ => {},
+        }
+        let notification_hub = NotificationHub::new(container.notification_bus.clone(), NotificationHubOption {
+            history_size: Some(app_config.server.sse_history_size),
+            max_connections_per_user: Some(app_config.server.sse_max_connections_per_user),
+        });
         let app_service = Arc::new(router::AppService {
-            notification_service: build_notification_service(dynamodb_client, dynamo_db_table_name),
+            notification_service: container.notification_service.clone(),
+            notification_hub,
+            health_service: health_service.clone(),
+            metrics_handle: metrics_handle.clone(),
         });
 
-        let router = router::construct(app_service);
+        let router = router::construct(app_service, &app_config.server.jwt_secret);
         // Run the server
-        let listener = tokio::net::TcpListener::bind("0.0.0.0:3001")
+        let listener = tokio::net::TcpListener::bind(("0.0.0.0", app_config.server.port))
             .await
             .unwrap();
         tracing::info!("listening on {}", listener.local_addr().unwrap());
-        axum::serve(listener, router).await.unwrap();
-    } else {
-        panic!("Invalid MEMO_MODULE value: {}", memo_module)
+        let shutdown = supervisor.shutdown_signal();
+        supervisor.spawn_graceful("server", async move {
+            // in-flight requests are finished, long lived SSE streams are cut off by the shutdown timeout
+            if let Err(e) = axum::serve(listener, router).with_graceful_shutdown(shutdown).await {
+                tracing::error!("server Error: {:?}", e);
+            }
+        });
+    }
+
+    // without the SERVER the probes and metrics get a port of their own
+    if !components.contains(Component::Server) {
+        let listener = tokio::net::TcpListener::bind(("0.0.0.0", app_config.admin_port))
+            .await
+            .unwrap();
+        tracing::info!("admin listening on {}", listener.local_addr().unwrap());
+        let admin_router = health::routes(health_service).merge(metrics::routes(metrics_handle));
+        let shutdown = supervisor.shutdown_signal();
+        supervisor.spawn_graceful("admin server", async move {
+            if let Err(e) = axum::serve(listener, admin_router).with_graceful_shutdown(shutdown).await {
+                tracing::error!("admin server Error: {:?}", e);
+            }
+        });
     }
-}
\ No newline at end of file
+
+    let healthy = supervisor.run().await;
+    telemetry.shutdown();
+    if !healthy {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,45 @@
+use std::path::PathBuf;
+use async_trait::async_trait;
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+use crate::adapters::memo_events::processors::{model::DBNotifcation, event_type_processor::{ApplicationError, RetryableError, PermanentError}};
+
+// Destination for notifications that are about to expire, written before the DynamoDB TTL deletes the item
+#[async_trait]
+pub trait ArchiveSinkInterface {
+    async fn archive(&self, items: &[DBNotifcation]) -> Result<(), ApplicationError>;
+}
+
+// Appends one JSON document per line to a daily file in `directory`
+#[derive(Debug, Clone)]
+pub struct JsonlArchiveSink {
+    pub directory: PathBuf,
+}
+
+#[async_trait]
+impl ArchiveSinkInterface for JsonlArchiveSink {
+    async fn archive(&self, items: &[DBNotifcation]) -> Result<(), ApplicationError> {
+        if items.is_empty() {
+            return Ok(());
+        }
+        let mut lines = String::new();
+        for item in items {
+            let line = serde_json::to_string(item).map_err(|e| PermanentError::new(&e.to_string()))?;
+            lines.push_str(&line);
+            lines.push('\n');
+        }
+
+        tokio::fs::create_dir_all(&self.directory).await.map_err(|e| RetryableError::new(&e.to_string()))?;
+        let path = self.directory.join(format!("notifications-{}.jsonl", chrono::Utc::now().format("%Y-%m-%d")));
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .map_err(|e| RetryableError::new(&e.to_string()))?;
+        file.write_all(lines.as_bytes()).await.map_err(|e| RetryableError::new(&e.to_string()))?;
+        file.sync_data().await.map_err(|e| RetryableError::new(&e.to_string()))?;
+
+        Ok(())
+    }
+}
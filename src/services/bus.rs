@@ -0,0 +1,110 @@
+use std::sync::Arc;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::{
+    adapters::memo_events::processors::model::{DBNotifcation, NotificationStatus},
+    errors::main::SerializationError,
+};
+
+const LOCAL_BUS_CAPACITY: usize = 1024;
+
+// Change signals emitted after a write lands, consumers use them to drop cached reads or push to clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum NotificationEvent {
+    Created(Box<DBNotifcation>),
+    StatusChanged {
+        user_id: String,
+        notification_id: String,
+        status: NotificationStatus,
+    },
+    Deleted {
+        user_id: String,
+        notification_id: String,
+    },
+    CountChanged {
+        user_id: String,
+    },
+}
+
+impl NotificationEvent {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            NotificationEvent::Created(_) => "CREATED",
+            NotificationEvent::StatusChanged { .. } => "STATUS_CHANGED",
+            NotificationEvent::Deleted { .. } => "DELETED",
+            NotificationEvent::CountChanged { .. } => "COUNT_CHANGED",
+        }
+    }
+
+    pub fn user_id(&self) -> &str {
+        match self {
+            NotificationEvent::Created(notification) => &notification.user_id,
+            NotificationEvent::StatusChanged { user_id, .. } => user_id,
+            NotificationEvent::Deleted { user_id, .. } => user_id,
+            NotificationEvent::CountChanged { user_id } => user_id,
+        }
+    }
+}
+
+// How changes made by one process reach the others
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChangeBusMode {
+    // in-process only, enough when READER and SERVER do not run as separate processes
+    Local,
+    // the READER forwards its changes over a Unix domain socket the SERVER listens on, for single-host setups
+    Uds,
+    // the SERVER reads every change from the table's DynamoDB stream, whoever wrote it
+    DynamoDbStreams,
+}
+
+impl ChangeBusMode {
+    pub fn parse(value: &str) -> Result<Self, SerializationError> {
+        match value {
+            "LOCAL" => Ok(ChangeBusMode::Local),
+            "UDS" => Ok(ChangeBusMode::Uds),
+            "DYNAMODB_STREAMS" => Ok(ChangeBusMode::DynamoDbStreams),
+            _ => Err(SerializationError::new(&format!("invalid change bus {:?}, expected LOCAL, UDS or DYNAMODB_STREAMS", value))),
+        }
+    }
+}
+
+#[async_trait]
+pub trait NotificationBusInterface {
+    async fn publish(&self, event: NotificationEvent);
+    fn subscribe(&self) -> broadcast::Receiver<NotificationEvent>;
+}
+
+pub type SharedNotificationBus = Arc<dyn NotificationBusInterface + Send + Sync>;
+
+// In-process bus, subscribers that fall behind miss events and see `RecvError::Lagged`
+pub struct LocalNotificationBus {
+    sender: broadcast::Sender<NotificationEvent>,
+}
+
+impl LocalNotificationBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(LOCAL_BUS_CAPACITY);
+        LocalNotificationBus { sender }
+    }
+}
+
+impl Default for LocalNotificationBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl NotificationBusInterface for LocalNotificationBus {
+    async fn publish(&self, event: NotificationEvent) {
+        // no subscribers is fine, nobody has anything to invalidate
+        let _ = self.sender.send(event);
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<NotificationEvent> {
+        self.sender.subscribe()
+    }
+}
@@ -0,0 +1,244 @@
+use std::{num::NonZeroUsize, sync::{Arc, Mutex}, time::{Duration, Instant}};
+use async_trait::async_trait;
+use lru::LruCache;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    client::eventbridge_client::EventPublisherInterface,
+    adapters::{
+        memo_api::router::{BulkUpdateNotificationBody, UpdateNotificationBody},
+        memo_events::processors::{event_type_processor::ApplicationError, model::{BulkUpdateOutcome, CreateMessageBody, DBNotifcation}},
+    },
+    services::{
+        archive::ArchiveSinkInterface,
+        bus::SharedNotificationBus,
+        keys::{EntityKey, NotificationKey, UserKey},
+        notification::{NotificationServiceInterface, SharedNotificationService},
+    },
+};
+
+#[derive(Debug, Clone)]
+pub struct NotificationCacheOption {
+    pub capacity: usize,
+    pub ttl_seconds: Option<u64>,
+}
+
+struct CacheEntry<T> {
+    value: T,
+    cached_at: Instant,
+}
+
+// Per user entries for the two hot reads, the first page and the unread count
+struct NotificationCache {
+    pages: LruCache<UserKey, CacheEntry<Vec<DBNotifcation>>>,
+    unread_counts: LruCache<UserKey, CacheEntry<i64>>,
+    ttl: Duration,
+    // set from `last_generation` on every invalidation of the user, a read that started before one must not cache its result
+    generations: LruCache<UserKey, u64>,
+    last_generation: u64,
+    // the highest generation no longer tracked, users without an entry are at this one
+    evicted_generation: u64,
+}
+
+impl NotificationCache {
+    fn new(capacity: NonZeroUsize, ttl: Duration) -> Self {
+        NotificationCache {
+            pages: LruCache::new(capacity),
+            unread_counts: LruCache::new(capacity),
+            ttl,
+            generations: LruCache::new(capacity),
+            last_generation: 0,
+            evicted_generation: 0,
+        }
+    }
+
+    fn generation(&self, user_key: &UserKey) -> u64 {
+        self.generations.peek(user_key).copied().unwrap_or(self.evicted_generation)
+    }
+
+    fn invalidate(&mut self, user_key: &UserKey) {
+        self.last_generation += 1;
+        if let Some((evicted_key, evicted)) = self.generations.push(user_key.clone(), self.last_generation) {
+            if &evicted_key != user_key {
+                self.evicted_generation = self.evicted_generation.max(evicted);
+            }
+        }
+        self.pages.pop(user_key);
+        self.unread_counts.pop(user_key);
+    }
+
+    fn clear(&mut self) {
+        self.last_generation += 1;
+        self.evicted_generation = self.last_generation;
+        self.generations.clear();
+        self.pages.clear();
+        self.unread_counts.clear();
+    }
+}
+
+// Read-through cache in front of the notification service. Entries are dropped when the bus
+// reports a change for the user, the TTL bounds staleness for writes the bus never sees
+pub struct CachedNotificationService {
+    inner: SharedNotificationService,
+    cache: Arc<Mutex<NotificationCache>>,
+}
+
+impl CachedNotificationService {
+    pub fn new(inner: SharedNotificationService, notification_bus: SharedNotificationBus, option: NotificationCacheOption) -> Self {
+        let capacity = NonZeroUsize::new(option.capacity).unwrap_or(NonZeroUsize::MIN);
+        let cache = Arc::new(Mutex::new(NotificationCache::new(capacity, Duration::from_secs(option.ttl_seconds.unwrap_or(30)))));
+
+        let mut receiver = notification_bus.subscribe();
+        let invalidated = cache.clone();
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => {
+                        if let Ok(user_key) = UserKey::new(event.user_id()) {
+                            invalidated.lock().unwrap().invalidate(&user_key);
+                        }
+                    },
+                    // we cannot tell which users the missed events were for
+                    Err(RecvError::Lagged(missed)) => {
+                        tracing::error!("notification cache missed {} invalidations, clearing", missed);
+                        invalidated.lock().unwrap().clear();
+                    },
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+
+        CachedNotificationService { inner, cache }
+    }
+
+    fn invalidate(&self, user_key: &UserKey) {
+        self.cache.lock().unwrap().invalidate(user_key);
+    }
+}
+
+#[async_trait]
+impl NotificationServiceInterface for CachedNotificationService {
+    async fn create_notification_message(&self, body: CreateMessageBody) -> Result<(), ApplicationError> {
+        self.inner.create_notification_message(body).await
+    }
+
+    async fn create_fan_out_notification_messages(&self, body: CreateMessageBody) -> Result<usize, ApplicationError> {
+        self.inner.create_fan_out_notification_messages(body).await
+    }
+
+    async fn get_notification_by_user_id(&self, user_key: UserKey) -> Result<Vec<DBNotifcation>, ApplicationError> {
+        let generation = {
+            let mut cache = self.cache.lock().unwrap();
+            let ttl = cache.ttl;
+            if let Some(entry) = cache.pages.get(&user_key) {
+                if entry.cached_at.elapsed() < ttl {
+                    return Ok(entry.value.clone());
+                }
+            }
+            cache.generation(&user_key)
+        };
+
+        let cached_at = Instant::now();
+        let notifications = self.inner.get_notification_by_user_id(user_key.clone()).await?;
+        let mut cache = self.cache.lock().unwrap();
+        if cache.generation(&user_key) == generation {
+            cache.pages.put(user_key, CacheEntry { value: notifications.clone(), cached_at });
+        }
+        Ok(notifications)
+    }
+
+    async fn update_notification_message(&self, user_key: UserKey, noti_key: NotificationKey, payload: UpdateNotificationBody) -> Result<DBNotifcation, ApplicationError> {
+        let result = self.inner.update_notification_message(user_key.clone(), noti_key, payload).await;
+        // drop the entries right away so the caller reads its own write, the bus event may still be in flight
+        self.invalidate(&user_key);
+        result
+    }
+
+    async fn get_unread_count(&self, user_key: UserKey) -> Result<i64, ApplicationError> {
+        let generation = {
+            let mut cache = self.cache.lock().unwrap();
+            let ttl = cache.ttl;
+            if let Some(entry) = cache.unread_counts.get(&user_key) {
+                if entry.cached_at.elapsed() < ttl {
+                    return Ok(entry.value);
+                }
+            }
+            cache.generation(&user_key)
+        };
+
+        let cached_at = Instant::now();
+        let count = self.inner.get_unread_count(user_key.clone()).await?;
+        let mut cache = self.cache.lock().unwrap();
+        if cache.generation(&user_key) == generation {
+            cache.unread_counts.put(user_key, CacheEntry { value: count, cached_at });
+        }
+        Ok(count)
+    }
+
+    async fn reconcile_unread_counts(&self) -> Result<usize, ApplicationError> {
+        self.inner.reconcile_unread_counts().await
+    }
+
+    async fn bulk_update_notification_messages(&self, user_key: UserKey, payload: BulkUpdateNotificationBody) -> Result<Vec<BulkUpdateOutcome>, ApplicationError> {
+        let result = self.inner.bulk_update_notification_messages(user_key.clone(), payload).await;
+        self.invalidate(&user_key);
+        result
+    }
+
+    async fn archive_expiring_notifications(&self, sink: &(dyn ArchiveSinkInterface + Send + Sync), horizon: i64) -> Result<usize, ApplicationError> {
+        self.inner.archive_expiring_notifications(sink, horizon).await
+    }
+
+    async fn relay_outbox_events(&self, publisher: &(dyn EventPublisherInterface + Send + Sync)) -> Result<usize, ApplicationError> {
+        self.inner.relay_outbox_events(publisher).await
+    }
+
+    async fn subscribe_topic(&self, user_key: UserKey, topic_id: String) -> Result<(), ApplicationError> {
+        self.inner.subscribe_topic(user_key, topic_id).await
+    }
+
+    async fn get_topic_subscriptions(&self, user_key: UserKey) -> Result<Vec<String>, ApplicationError> {
+        self.inner.get_topic_subscriptions(user_key).await
+    }
+
+    async fn may_follow_topic(&self, user_key: UserKey, topic_id: String) -> Result<bool, ApplicationError> {
+        self.inner.may_follow_topic(user_key, topic_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: &str) -> UserKey {
+        UserKey::new(id.to_string()).unwrap()
+    }
+
+    #[test]
+    fn invalidation_only_moves_the_users_generation() {
+        let mut cache = NotificationCache::new(NonZeroUsize::new(2).unwrap(), Duration::from_secs(30));
+        let (alice, bob) = (user("alice"), user("bob"));
+        let started = (cache.generation(&alice), cache.generation(&bob));
+
+        cache.invalidate(&bob);
+        assert_eq!(cache.generation(&alice), started.0);
+        assert_ne!(cache.generation(&bob), started.1);
+    }
+
+    #[test]
+    fn evicted_generations_never_look_unchanged() {
+        let mut cache = NotificationCache::new(NonZeroUsize::new(2).unwrap(), Duration::from_secs(30));
+        let alice = user("alice");
+        let started = cache.generation(&alice);
+
+        // alice's invalidation is pushed out by other users before her read completes
+        cache.invalidate(&alice);
+        cache.invalidate(&user("bob"));
+        cache.invalidate(&user("carol"));
+        assert_ne!(cache.generation(&alice), started);
+
+        let started = cache.generation(&alice);
+        cache.clear();
+        assert_ne!(cache.generation(&alice), started);
+    }
+}
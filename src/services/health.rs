@@ -0,0 +1,119 @@
+use std::{sync::{Arc, atomic::{AtomicI64, Ordering}}, time::Duration};
+use aws_sdk_dynamodb::{Client as DynamoDbClient, error::DisplayErrorContext};
+use aws_sdk_sqs::{Client as SQSClient, types::QueueAttributeName};
+use chrono::Utc;
+use serde::Serialize;
+use tokio::time::timeout;
+
+// a dependency that does not answer within this is reported as down
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Last time a loop made progress, epoch millis, 0 until the first beat
+#[derive(Default)]
+pub struct Heartbeat {
+    last_beat_millis: AtomicI64,
+}
+
+impl Heartbeat {
+    pub fn beat(&self) {
+        self.last_beat_millis.store(Utc::now().timestamp_millis(), Ordering::SeqCst);
+    }
+
+    pub fn age(&self) -> Option<Duration> {
+        let last_beat_millis = self.last_beat_millis.load(Ordering::SeqCst);
+        if last_beat_millis == 0 {
+            return None;
+        }
+        let age_millis = (Utc::now().timestamp_millis() - last_beat_millis).max(0);
+        Some(Duration::from_millis(age_millis as u64))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub ok: bool,
+    pub detail: String,
+}
+
+pub struct HealthServiceOption {
+    pub dynamodb_client: DynamoDbClient,
+    pub table_name: String,
+    // the queue is only checked where the poller runs
+    pub sqs_client: Option<SQSClient>,
+    pub queue_url: Option<String>,
+    pub poller_heartbeat: Option<Arc<Heartbeat>>,
+    pub heartbeat_max_age_seconds: Option<u64>,
+}
+
+// Answers readiness probes by checking what this process needs to do its work
+pub struct HealthService {
+    dynamodb_client: DynamoDbClient,
+    table_name: String,
+    queue: Option<(SQSClient, String)>,
+    poller_heartbeat: Option<Arc<Heartbeat>>,
+    heartbeat_max_age: Duration,
+}
+
+impl HealthService {
+    pub fn new(option: HealthServiceOption) -> Self {
+        HealthService {
+            dynamodb_client: option.dynamodb_client,
+            table_name: option.table_name,
+            queue: option.sqs_client.zip(option.queue_url),
+            poller_heartbeat: option.poller_heartbeat,
+            heartbeat_max_age: Duration::from_secs(option.heartbeat_max_age_seconds.unwrap_or(120)),
+        }
+    }
+
+    pub async fn readiness(&self) -> Vec<CheckResult> {
+        let (table, queue) = tokio::join!(self.check_table(), self.check_queue());
+        let mut checks = vec![table];
+        checks.extend(queue);
+        checks.extend(self.check_poller());
+        checks
+    }
+
+    async fn check_table(&self) -> CheckResult {
+        let describe = self.dynamodb_client.describe_table().table_name(self.table_name.clone()).send();
+        let (ok, detail) = match timeout(CHECK_TIMEOUT, describe).await {
+            Ok(Ok(result)) => {
+                let status = result.table().and_then(|table| table.table_status()).map(|status| status.as_str().to_string());
+                (true, status.unwrap_or_else(|| "UNKNOWN".to_string()))
+            },
+            Ok(Err(e)) => (false, DisplayErrorContext(&e).to_string()),
+            Err(_) => (false, format!("no answer within {:?}", CHECK_TIMEOUT)),
+        };
+        CheckResult { name: "dynamodb", ok, detail }
+    }
+
+    async fn check_queue(&self) -> Option<CheckResult> {
+        let (sqs_client, queue_url) = self.queue.as_ref()?;
+        let attributes = sqs_client.get_queue_attributes()
+            .queue_url(queue_url.clone())
+            .attribute_names(QueueAttributeName::ApproximateNumberOfMessages)
+            .send();
+        let (ok, detail) = match timeout(CHECK_TIMEOUT, attributes).await {
+            Ok(Ok(result)) => {
+                let waiting = result.attributes()
+                    .and_then(|attributes| attributes.get(&QueueAttributeName::ApproximateNumberOfMessages))
+                    .cloned()
+                    .unwrap_or_else(|| "unknown".to_string());
+                (true, format!("{} messages waiting", waiting))
+            },
+            Ok(Err(e)) => (false, DisplayErrorContext(&e).to_string()),
+            Err(_) => (false, format!("no answer within {:?}", CHECK_TIMEOUT)),
+        };
+        Some(CheckResult { name: "sqs", ok, detail })
+    }
+
+    fn check_poller(&self) -> Option<CheckResult> {
+        let heartbeat = self.poller_heartbeat.as_ref()?;
+        let (ok, detail) = match heartbeat.age() {
+            Some(age) if age <= self.heartbeat_max_age => (true, format!("last poll {}s ago", age.as_secs())),
+            Some(age) => (false, format!("last poll {}s ago, more than {}s", age.as_secs(), self.heartbeat_max_age.as_secs())),
+            None => (false, "no poll finished yet".to_string()),
+        };
+        Some(CheckResult { name: "poller", ok, detail })
+    }
+}
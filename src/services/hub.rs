@@ -0,0 +1,249 @@
+use std::{collections::{HashMap, VecDeque}, num::NonZeroUsize, sync::{Arc, Mutex}};
+use lru::LruCache;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::services::bus::{NotificationEvent, SharedNotificationBus};
+
+const HUB_CHANNEL_CAPACITY: usize = 1024;
+const HUB_MAX_USERS_WITH_HISTORY: usize = 10_000;
+
+pub struct NotificationHubOption {
+    // events kept per user for `Last-Event-ID` resume
+    pub history_size: Option<usize>,
+    pub max_connections_per_user: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct HubEvent {
+    pub id: u64,
+    pub event: NotificationEvent,
+}
+
+// Live events for one connection, `replay` holds what it missed since the id it resumed from
+pub struct HubSubscription {
+    pub guard: ConnectionGuard,
+    pub replay: Vec<HubEvent>,
+    pub receiver: broadcast::Receiver<HubEvent>,
+}
+
+// Counts a connection against the per user limit until dropped
+pub struct ConnectionGuard {
+    hub: Arc<NotificationHub>,
+    user_id: String,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut users = self.hub.users.lock().unwrap();
+        if let Some(channel) = users.get_mut(&self.user_id) {
+            channel.connections -= 1;
+            // the user's channel goes with their last connection
+            if channel.connections == 0 {
+                users.remove(&self.user_id);
+            }
+        }
+    }
+}
+
+// The open connections of one user and the channel that carries their events
+struct UserChannel {
+    connections: usize,
+    sender: broadcast::Sender<HubEvent>,
+}
+
+struct HubState {
+    next_id: u64,
+    history: LruCache<String, VecDeque<HubEvent>>,
+}
+
+// Numbers the bus events and fans them out to the push connections of this process. Every user with
+// a connection has a channel of their own, a connection never sees other users' traffic except
+// for new messages on the topics it follows
+pub struct NotificationHub {
+    state: Mutex<HubState>,
+    // locked after `state` when both are needed
+    users: Mutex<HashMap<String, UserChannel>>,
+    topics: Mutex<HashMap<String, broadcast::Sender<HubEvent>>>,
+    history_size: usize,
+    max_connections_per_user: usize,
+}
+
+impl NotificationHub {
+    pub fn new(notification_bus: SharedNotificationBus, option: NotificationHubOption) -> Arc<Self> {
+        let hub = Arc::new(NotificationHub {
+            state: Mutex::new(HubState {
+                // ids keep growing across restarts, so a stale Last-Event-ID never looks like a future one
+                next_id: chrono::Utc::now().timestamp_millis() as u64 * 1000,
+                history: LruCache::new(NonZeroUsize::new(HUB_MAX_USERS_WITH_HISTORY).unwrap()),
+            }),
+            users: Mutex::new(HashMap::new()),
+            topics: Mutex::new(HashMap::new()),
+            history_size: option.history_size.unwrap_or(100),
+            max_connections_per_user: option.max_connections_per_user.unwrap_or(5),
+        });
+
+        let mut receiver = notification_bus.subscribe();
+        let forwarder = hub.clone();
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => forwarder.dispatch(event),
+                    Err(RecvError::Lagged(missed)) => tracing::error!("notification hub missed {} events", missed),
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+
+        hub
+    }
+
+    fn dispatch(&self, event: NotificationEvent) {
+        // numbering, history and send happen under one lock so a subscriber sees every event exactly once
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+        let hub_event = HubEvent { id: state.next_id, event };
+        let user_id = hub_event.event.user_id().to_string();
+        let history = state.history.get_or_insert_mut(user_id.clone(), VecDeque::new);
+        history.push_back(hub_event.clone());
+        if history.len() > self.history_size {
+            history.pop_front();
+        }
+        if let NotificationEvent::Created(notification) = &hub_event.event {
+            let mut topics = self.topics.lock().unwrap();
+            // a send fails once the topic's last follower is gone
+            if topics.get(&notification.topic_id).is_some_and(|sender| sender.send(hub_event.clone()).is_err()) {
+                topics.remove(&notification.topic_id);
+            }
+        }
+        if let Some(channel) = self.users.lock().unwrap().get(&user_id) {
+            let _ = channel.sender.send(hub_event);
+        }
+    }
+
+    // None once the user already has the maximum number of connections open
+    pub fn subscribe(self: &Arc<Self>, user_id: &str, last_event_id: Option<u64>) -> Option<HubSubscription> {
+        // under the state lock no event is dispatched between the replay and the first live one
+        let mut state = self.state.lock().unwrap();
+        let receiver = {
+            let mut users = self.users.lock().unwrap();
+            if users.get(user_id).map_or(0, |channel| channel.connections) >= self.max_connections_per_user {
+                return None;
+            }
+            let channel = users.entry(user_id.to_string()).or_insert_with(|| UserChannel {
+                connections: 0,
+                sender: broadcast::channel(HUB_CHANNEL_CAPACITY).0,
+            });
+            channel.connections += 1;
+            channel.sender.subscribe()
+        };
+        let guard = ConnectionGuard { hub: self.clone(), user_id: user_id.to_string() };
+
+        let replay = match last_event_id {
+            Some(last_event_id) => state.history.get(user_id)
+                .map(|history| history.iter().filter(|event| event.id > last_event_id).cloned().collect())
+                .unwrap_or_default(),
+            None => vec![],
+        };
+
+        Some(HubSubscription { guard, replay, receiver })
+    }
+
+    // New messages on a topic from now on, whoever they were sent to
+    pub fn follow_topic(&self, topic_id: &str) -> broadcast::Receiver<HubEvent> {
+        let mut topics = self.topics.lock().unwrap();
+        topics.entry(topic_id.to_string())
+            .or_insert_with(|| broadcast::channel(HUB_CHANNEL_CAPACITY).0)
+            .subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::bus::LocalNotificationBus;
+
+    fn count_changed(user_id: &str) -> NotificationEvent {
+        NotificationEvent::CountChanged { user_id: user_id.to_string() }
+    }
+
+    fn created(user_id: &str, topic_id: &str) -> NotificationEvent {
+        let notification = serde_json::from_value(serde_json::json!({
+            "SK": format!("message-{}", user_id), "PK": user_id,
+            "replyer_id": "replyer", "replyer_avatar": "", "replyer_name": "Replyer",
+            "notification_type": "Message", "status": "UNREAD",
+            "topic_id": topic_id, "message_id": "message", "content": "hello",
+            "created_time": "2024-01-01T00:00:00+00:00", "updated_time": null,
+        })).unwrap();
+        NotificationEvent::Created(Box::new(notification))
+    }
+
+    #[tokio::test]
+    async fn replays_the_users_events_after_the_last_id() {
+        let bus: SharedNotificationBus = Arc::new(LocalNotificationBus::new());
+        let hub = NotificationHub::new(bus.clone(), NotificationHubOption { history_size: Some(2), max_connections_per_user: None });
+        let mut live = hub.subscribe("user-1", None).unwrap();
+        for user_id in ["user-1", "user-2", "user-1", "user-1"] {
+            bus.publish(count_changed(user_id)).await;
+        }
+        let mut ids = vec![];
+        for _ in 0..3 {
+            ids.push(live.receiver.recv().await.unwrap().id);
+        }
+        // user-2's event never reaches user-1's connection
+        assert!(live.receiver.try_recv().is_err());
+
+        // only the last two events of user-1 are kept, in order
+        let resumed = hub.subscribe("user-1", Some(ids[0] - 1)).unwrap();
+        assert_eq!(resumed.replay.iter().map(|event| event.id).collect::<Vec<_>>(), vec![ids[1], ids[2]]);
+        let resumed = hub.subscribe("user-1", Some(ids[1])).unwrap();
+        assert_eq!(resumed.replay.iter().map(|event| event.id).collect::<Vec<_>>(), vec![ids[2]]);
+        assert!(resumed.replay.iter().all(|event| event.event.user_id() == "user-1"));
+        // a fresh connection gets live events only
+        assert!(hub.subscribe("user-1", None).unwrap().replay.is_empty());
+    }
+
+    #[tokio::test]
+    async fn drops_a_users_channel_with_their_last_connection() {
+        let bus: SharedNotificationBus = Arc::new(LocalNotificationBus::new());
+        let hub = NotificationHub::new(bus, NotificationHubOption { history_size: None, max_connections_per_user: None });
+        let first = hub.subscribe("user-1", None).unwrap();
+        let second = hub.subscribe("user-1", None).unwrap();
+        drop(first);
+        assert!(hub.users.lock().unwrap().contains_key("user-1"));
+        drop(second);
+        assert!(hub.users.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn followers_get_new_messages_on_the_topic() {
+        let bus: SharedNotificationBus = Arc::new(LocalNotificationBus::new());
+        let hub = NotificationHub::new(bus.clone(), NotificationHubOption { history_size: None, max_connections_per_user: None });
+        let mut followed = hub.follow_topic("topic-1");
+        bus.publish(created("user-2", "topic-2")).await;
+        bus.publish(count_changed("user-2")).await;
+        bus.publish(created("user-3", "topic-1")).await;
+
+        let event = followed.recv().await.unwrap();
+        assert_eq!(event.event.user_id(), "user-3");
+        assert!(followed.try_recv().is_err());
+
+        // the next message after the last follower left drops the topic's channel
+        drop(followed);
+        let mut recipient = hub.subscribe("user-3", None).unwrap();
+        bus.publish(created("user-3", "topic-1")).await;
+        // the recipient's copy is sent after the topic's, so the topic was handled by now
+        recipient.receiver.recv().await.unwrap();
+        assert!(hub.topics.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn limits_connections_per_user() {
+        let bus: SharedNotificationBus = Arc::new(LocalNotificationBus::new());
+        let hub = NotificationHub::new(bus, NotificationHubOption { history_size: None, max_connections_per_user: Some(1) });
+        let first = hub.subscribe("user-1", None).unwrap();
+        assert!(hub.subscribe("user-1", None).is_none());
+        assert!(hub.subscribe("user-2", None).is_some());
+        drop(first);
+        assert!(hub.subscribe("user-1", None).is_some());
+    }
+}
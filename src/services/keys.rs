@@ -0,0 +1,320 @@
+use aws_sdk_dynamodb::types::AttributeValue;
+
+use crate::errors::main::SerializationError;
+
+// Single-table key layout, every PK/SK value is `<PREFIX>#<id>`:
+//   PK = USR#<user_id>   SK = NTF#<notification_id>   the notification
+//   PK = USR#<user_id>   SK = CNT#UNREAD              the unread counter
+//   PK = USR#<user_id>   SK = SRC#<upstream id>       maps an upstream notification id to a generated one
+//   PK = USR#<user_id>   SK = SUB#<topic_id>          topic the user follows
+//   PK = SYS#MIGRATIONS  SK = MIG#<version>           data migration ledger
+//   PK = OBX#<shard>     SK = EVT#<ulid>              domain event waiting to be relayed
+//   PK = OBX#<shard>     SK = FEV#<ulid>              domain event the bus can never accept, kept for inspection
+// New entities add a key type here instead of formatting prefixes at the call site.
+pub const KEY_DELIMITER: char = '#';
+
+// GSI on the notification items, PK = USR#<user_id> and sort key `created_time`, the name is the
+// default of DYNAMODB_CREATED_TIME_INDEX
+pub const USER_CREATED_TIME_INDEX: &str = "PK-created_time-index";
+
+// Outbox events are spread over a few partitions so busy users do not share one hot key
+pub const OUTBOX_SHARDS: u32 = 8;
+
+// Where the notification id in SK comes from
+#[derive(Debug, Clone, PartialEq)]
+pub enum NotificationIdMode {
+    // the upstream `notification_id`, lists are ordered through USER_CREATED_TIME_INDEX
+    Upstream,
+    // a ULID generated at creation, so SK itself is time-ordered and lists read the base table.
+    // Items written before switching keep their upstream id and do not sort by time.
+    Ulid,
+}
+
+impl NotificationIdMode {
+    pub fn parse(value: &str) -> Result<Self, SerializationError> {
+        match value {
+            "UPSTREAM" => Ok(NotificationIdMode::Upstream),
+            "ULID" => Ok(NotificationIdMode::Ulid),
+            _ => Err(SerializationError::new(&format!("invalid notification id mode {:?}, expected UPSTREAM or ULID", value))),
+        }
+    }
+}
+
+pub trait EntityKey: Sized {
+    const PREFIX: &'static str;
+
+    fn from_valid_id(id: String) -> Self;
+    fn id(&self) -> &str;
+
+    // Rejects ids that would make the encoded key ambiguous
+    fn new(id: impl Into<String>) -> Result<Self, SerializationError> {
+        let id = id.into();
+        if id.is_empty() {
+            return Err(SerializationError::new(&format!("{} key id must not be empty", Self::PREFIX)));
+        }
+        if id.contains(KEY_DELIMITER) {
+            return Err(SerializationError::new(&format!("{} key id {:?} must not contain {:?}", Self::PREFIX, id, KEY_DELIMITER)));
+        }
+        Ok(Self::from_valid_id(id))
+    }
+
+    fn decode(value: &str) -> Result<Self, SerializationError> {
+        let id = value
+            .strip_prefix(Self::PREFIX)
+            .and_then(|rest| rest.strip_prefix(KEY_DELIMITER))
+            .ok_or_else(|| SerializationError::new(&format!("key {:?} is not a {} key", value, Self::PREFIX)))?;
+        Self::new(id)
+    }
+
+    // `<PREFIX>#`, for begins_with conditions over all keys of this type
+    fn key_prefix() -> String {
+        format!("{}{}", Self::PREFIX, KEY_DELIMITER)
+    }
+
+    fn encode(&self) -> String {
+        format!("{}{}", Self::key_prefix(), self.id())
+    }
+
+    fn attribute(&self) -> AttributeValue {
+        AttributeValue::S(self.encode())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UserKey(String);
+
+impl EntityKey for UserKey {
+    const PREFIX: &'static str = "USR";
+
+    fn from_valid_id(id: String) -> Self {
+        UserKey(id)
+    }
+
+    fn id(&self) -> &str {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NotificationKey(String);
+
+impl EntityKey for NotificationKey {
+    const PREFIX: &'static str = "NTF";
+
+    fn from_valid_id(id: String) -> Self {
+        NotificationKey(id)
+    }
+
+    fn id(&self) -> &str {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CounterKey(String);
+
+impl CounterKey {
+    pub fn unread() -> Self {
+        CounterKey("UNREAD".to_string())
+    }
+}
+
+impl EntityKey for CounterKey {
+    const PREFIX: &'static str = "CNT";
+
+    fn from_valid_id(id: String) -> Self {
+        CounterKey(id)
+    }
+
+    fn id(&self) -> &str {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SourceNotificationKey(String);
+
+impl EntityKey for SourceNotificationKey {
+    const PREFIX: &'static str = "SRC";
+
+    fn from_valid_id(id: String) -> Self {
+        SourceNotificationKey(id)
+    }
+
+    fn id(&self) -> &str {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SubscriptionKey(String);
+
+impl EntityKey for SubscriptionKey {
+    const PREFIX: &'static str = "SUB";
+
+    fn from_valid_id(id: String) -> Self {
+        SubscriptionKey(id)
+    }
+
+    fn id(&self) -> &str {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SystemKey(String);
+
+impl SystemKey {
+    pub fn migrations() -> Self {
+        SystemKey("MIGRATIONS".to_string())
+    }
+}
+
+impl EntityKey for SystemKey {
+    const PREFIX: &'static str = "SYS";
+
+    fn from_valid_id(id: String) -> Self {
+        SystemKey(id)
+    }
+
+    fn id(&self) -> &str {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MigrationKey(String);
+
+impl EntityKey for MigrationKey {
+    const PREFIX: &'static str = "MIG";
+
+    fn from_valid_id(id: String) -> Self {
+        MigrationKey(id)
+    }
+
+    fn id(&self) -> &str {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OutboxKey(String);
+
+impl OutboxKey {
+    // Events of one user always land in the same shard, so they are relayed in order
+    pub fn for_user(user_key: &UserKey) -> Self {
+        let hash = user_key.id().bytes().fold(0u32, |hash, byte| hash.wrapping_mul(31).wrapping_add(byte as u32));
+        OutboxKey((hash % OUTBOX_SHARDS).to_string())
+    }
+
+    pub fn all() -> Vec<Self> {
+        (0..OUTBOX_SHARDS).map(|shard| OutboxKey(shard.to_string())).collect()
+    }
+}
+
+impl EntityKey for OutboxKey {
+    const PREFIX: &'static str = "OBX";
+
+    fn from_valid_id(id: String) -> Self {
+        OutboxKey(id)
+    }
+
+    fn id(&self) -> &str {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EventKey(String);
+
+impl EntityKey for EventKey {
+    const PREFIX: &'static str = "EVT";
+
+    fn from_valid_id(id: String) -> Self {
+        EventKey(id)
+    }
+
+    fn id(&self) -> &str {
+        &self.0
+    }
+}
+
+// Same id as the event it replaces, outside the EVT# range the relay reads
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FailedEventKey(String);
+
+impl FailedEventKey {
+    pub fn for_event(event_key: &EventKey) -> Self {
+        FailedEventKey(event_key.id().to_string())
+    }
+}
+
+impl EntityKey for FailedEventKey {
+    const PREFIX: &'static str = "FEV";
+
+    fn from_valid_id(id: String) -> Self {
+        FailedEventKey(id)
+    }
+
+    fn id(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let user_key = UserKey::new("user-1").unwrap();
+        assert_eq!(user_key.encode(), "USR#user-1");
+        assert_eq!(UserKey::decode(&user_key.encode()).unwrap(), user_key);
+
+        let noti_key = NotificationKey::new("01HZX3N5W6").unwrap();
+        assert_eq!(noti_key.encode(), "NTF#01HZX3N5W6");
+        assert_eq!(NotificationKey::decode(&noti_key.encode()).unwrap(), noti_key);
+
+        assert_eq!(CounterKey::unread().encode(), "CNT#UNREAD");
+        assert_eq!(CounterKey::decode("CNT#UNREAD").unwrap(), CounterKey::unread());
+        assert_eq!(SystemKey::decode(&SystemKey::migrations().encode()).unwrap(), SystemKey::migrations());
+        // a failed event keeps its id but leaves the range the relay reads
+        let failed_key = FailedEventKey::for_event(&EventKey::new("01HZX3N5W6").unwrap());
+        assert_eq!(failed_key.encode(), "FEV#01HZX3N5W6");
+        assert!(!failed_key.encode().starts_with(&EventKey::key_prefix()));
+        assert_eq!(UserKey::new("user-1").unwrap().attribute(), AttributeValue::S("USR#user-1".to_string()));
+    }
+
+    #[test]
+    fn rejects_ambiguous_ids() {
+        assert!(UserKey::new("").is_err());
+        assert!(UserKey::new("a#b").is_err());
+        // the remainder after the prefix is validated like a new id
+        assert!(UserKey::decode("USR#a#b").is_err());
+        assert!(UserKey::decode("USR#").is_err());
+    }
+
+    #[test]
+    fn decode_checks_the_prefix() {
+        assert!(UserKey::decode("NTF#user-1").is_err());
+        assert!(UserKey::decode("USRuser-1").is_err());
+        assert!(UserKey::decode("USERS#user-1").is_err());
+        assert_eq!(NotificationKey::key_prefix(), "NTF#");
+    }
+
+    #[test]
+    fn outbox_shard_is_stable_per_user() {
+        let user_key = UserKey::new("user-1").unwrap();
+        let shard = OutboxKey::for_user(&user_key);
+        assert_eq!(OutboxKey::for_user(&user_key), shard);
+        assert!(OutboxKey::all().contains(&shard));
+        assert_eq!(OutboxKey::all().len(), OUTBOX_SHARDS as usize);
+    }
+
+    #[test]
+    fn parses_notification_id_mode() {
+        assert_eq!(NotificationIdMode::parse("UPSTREAM").unwrap(), NotificationIdMode::Upstream);
+        assert_eq!(NotificationIdMode::parse("ULID").unwrap(), NotificationIdMode::Ulid);
+        assert!(NotificationIdMode::parse("ulid").is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use aws_sdk_dynamodb::{Error as DynamoDbError, types::AttributeValue, operation::{query::QueryOutput, get_item::GetItemOutput, scan::ScanOutput}};
+use async_trait::async_trait;
+use std::{collections::HashMap, future::Future, sync::Arc, time::Instant};
+use tracing::Instrument;
+
+use crate::{
+    adapters::memo_events::processors::model::DBStatusChange,
+    services::{
+        keys::{UserKey, NotificationKey, OutboxKey, EventKey, SubscriptionKey},
+        metrics,
+        store::{DatabaseStoreInterface, StatusTransition, StatusChange, SourceMapping, BatchCreate, BatchCreated},
+    },
+};
+
+// Records latency and errors of every store operation and wraps it in a client span, the
+// operation label is the method name without its `db_` prefix
+pub struct MeasuredDatabaseStore {
+    inner: Arc<dyn DatabaseStoreInterface + Send + Sync>,
+}
+
+impl MeasuredDatabaseStore {
+    pub fn new(inner: Arc<dyn DatabaseStoreInterface + Send + Sync>) -> Self {
+        MeasuredDatabaseStore { inner }
+    }
+}
+
+async fn measure<T>(operation: &'static str, call: impl Future<Output = Result<T, DynamoDbError>>) -> Result<T, DynamoDbError> {
+    measure_with(operation, call, Result::is_ok).await
+}
+
+async fn measure_with<T>(operation: &'static str, call: impl Future<Output = T>, succeeded: impl Fn(&T) -> bool) -> T {
+    let span = tracing::info_span!("dynamodb", otel.name = %format!("dynamodb {}", operation), otel.kind = "client", db.system = "dynamodb", db.operation = operation);
+    let started = Instant::now();
+    let result = call.instrument(span).await;
+    metrics::observe_dynamodb(operation, succeeded(&result), started.elapsed());
+    result
+}
+
+#[async_trait]
+impl DatabaseStoreInterface for MeasuredDatabaseStore {
+    async fn db_create_notification_message(&self, user_key: UserKey, item: HashMap<String, AttributeValue>, condition: String, source: Option<SourceMapping>, outbox_item: HashMap<String, AttributeValue>) -> Result<(), DynamoDbError> {
+        measure("create_notification_message", self.inner.db_create_notification_message(user_key, item, condition, source, outbox_item)).await
+    }
+
+    async fn db_update_notification_status(&self, user_key: UserKey, noti_key: NotificationKey, change: StatusChange) -> Result<HashMap<String, AttributeValue>, DynamoDbError> {
+        measure("update_notification_status", self.inner.db_update_notification_status(user_key, noti_key, change)).await
+    }
+
+    async fn db_complete_status_change(&self, user_key: UserKey, noti_key: NotificationKey, change: DBStatusChange, outbox_item: HashMap<String, AttributeValue>) -> Result<(), DynamoDbError> {
+        measure("complete_status_change", self.inner.db_complete_status_change(user_key, noti_key, change, outbox_item)).await
+    }
+
+    async fn db_get_notifications_by_user_id(&self, user_key: UserKey) -> Result<QueryOutput, DynamoDbError> {
+        measure("get_notifications_by_user_id", self.inner.db_get_notifications_by_user_id(user_key)).await
+    }
+
+    async fn db_get_notification_item_with_pk_sk(&self, pk_value: String, sk_value: String) -> Result<GetItemOutput, DynamoDbError> {
+        measure("get_notification_item_with_pk_sk", self.inner.db_get_notification_item_with_pk_sk(pk_value, sk_value)).await
+    }
+
+    async fn db_get_unread_count(&self, user_key: UserKey) -> Result<GetItemOutput, DynamoDbError> {
+        measure("get_unread_count", self.inner.db_get_unread_count(user_key)).await
+    }
+
+    async fn db_get_notification_states(&self, user_key: UserKey) -> Result<Vec<HashMap<String, AttributeValue>>, DynamoDbError> {
+        measure("get_notification_states", self.inner.db_get_notification_states(user_key)).await
+    }
+
+    async fn db_set_unread_count(&self, user_key: UserKey, expected: i64, count: i64) -> Result<(), DynamoDbError> {
+        measure("set_unread_count", self.inner.db_set_unread_count(user_key, expected, count)).await
+    }
+
+    async fn db_scan_user_partitions(&self, exclusive_start_key: Option<HashMap<String, AttributeValue>>) -> Result<ScanOutput, DynamoDbError> {
+        measure("scan_user_partitions", self.inner.db_scan_user_partitions(exclusive_start_key)).await
+    }
+
+    async fn db_get_all_notifications_by_user_id(&self, user_key: UserKey, exclusive_start_key: Option<HashMap<String, AttributeValue>>) -> Result<QueryOutput, DynamoDbError> {
+        measure("get_all_notifications_by_user_id", self.inner.db_get_all_notifications_by_user_id(user_key, exclusive_start_key)).await
+    }
+
+    async fn db_batch_get_notifications(&self, user_key: UserKey, noti_keys: Vec<NotificationKey>) -> Result<Vec<HashMap<String, AttributeValue>>, DynamoDbError> {
+        measure("batch_get_notifications", self.inner.db_batch_get_notifications(user_key, noti_keys)).await
+    }
+
+    async fn db_bulk_update_notification_messages(&self, user_key: UserKey, updates: Vec<(NotificationKey, StatusTransition)>) -> Result<(), DynamoDbError> {
+        measure("bulk_update_notification_messages", self.inner.db_bulk_update_notification_messages(user_key, updates)).await
+    }
+
+    async fn db_scan_expiring_notifications(&self, horizon: i64, exclusive_start_key: Option<HashMap<String, AttributeValue>>) -> Result<ScanOutput, DynamoDbError> {
+        measure("scan_expiring_notifications", self.inner.db_scan_expiring_notifications(horizon, exclusive_start_key)).await
+    }
+
+    async fn db_mark_notification_archived(&self, user_key: UserKey, noti_key: NotificationKey, version: i64, archived_at: i64) -> Result<(), DynamoDbError> {
+        measure("mark_notification_archived", self.inner.db_mark_notification_archived(user_key, noti_key, version, archived_at)).await
+    }
+
+    async fn db_batch_create_notification_messages(&self, creates: Vec<BatchCreate>) -> BatchCreated {
+        // a fan-out never fails as a whole, it counts as an error when any recipient failed
+        measure_with("batch_create_notification_messages", self.inner.db_batch_create_notification_messages(creates), |batch| batch.failed.is_empty()).await
+    }
+
+    async fn db_get_outbox_events(&self, outbox_key: OutboxKey, limit: i32) -> Result<QueryOutput, DynamoDbError> {
+        measure("get_outbox_events", self.inner.db_get_outbox_events(outbox_key, limit)).await
+    }
+
+    async fn db_delete_outbox_event(&self, outbox_key: OutboxKey, event_key: EventKey) -> Result<(), DynamoDbError> {
+        measure("delete_outbox_event", self.inner.db_delete_outbox_event(outbox_key, event_key)).await
+    }
+
+    async fn db_fail_outbox_event(&self, outbox_key: OutboxKey, event_key: EventKey, outbox_item: HashMap<String, AttributeValue>, reason: String) -> Result<(), DynamoDbError> {
+        measure("fail_outbox_event", self.inner.db_fail_outbox_event(outbox_key, event_key, outbox_item, reason)).await
+    }
+
+    async fn db_put_topic_subscription(&self, user_key: UserKey, subscription_key: SubscriptionKey) -> Result<(), DynamoDbError> {
+        measure("put_topic_subscription", self.inner.db_put_topic_subscription(user_key, subscription_key)).await
+    }
+
+    async fn db_get_topic_subscriptions(&self, user_key: UserKey) -> Result<Vec<HashMap<String, AttributeValue>>, DynamoDbError> {
+        measure("get_topic_subscriptions", self.inner.db_get_topic_subscriptions(user_key)).await
+    }
+
+    async fn db_has_topic_notification(&self, user_key: UserKey, topic_id: String) -> Result<bool, DynamoDbError> {
+        measure("has_topic_notification", self.inner.db_has_topic_notification(user_key, topic_id)).await
+    }
+}
@@ -0,0 +1,69 @@
+use std::time::Duration;
+use metrics::{counter, describe_counter, describe_gauge, describe_histogram, gauge, histogram, Unit};
+use metrics_exporter_prometheus::{BuildError, PrometheusBuilder, PrometheusHandle};
+
+// seconds, from a cached read up to a slow batch write
+const LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+// How far a queue message got, each stage is its own counter
+pub enum MessageStage {
+    Received,
+    // deleted from the queue, processed or dropped as a lost conflict
+    Acked,
+    // left on the queue to be received again
+    Retried,
+    DeadLettered,
+}
+
+impl MessageStage {
+    fn metric_name(&self) -> &'static str {
+        match self {
+            MessageStage::Received => "memo_messages_received_total",
+            MessageStage::Acked => "memo_messages_acked_total",
+            MessageStage::Retried => "memo_messages_retried_total",
+            MessageStage::DeadLettered => "memo_messages_dead_lettered_total",
+        }
+    }
+}
+
+// Installs the global recorder, until then the functions below record nothing.
+// Histograms are only drained by `run_upkeep`, the caller has to run it periodically
+pub fn install() -> Result<PrometheusHandle, BuildError> {
+    let handle = PrometheusBuilder::new()
+        .set_buckets(LATENCY_BUCKETS)?
+        .install_recorder()?;
+    describe_counter!("memo_messages_received_total", "Queue messages received, per event type");
+    describe_counter!("memo_messages_acked_total", "Queue messages deleted after handling, per event type");
+    describe_counter!("memo_messages_retried_total", "Queue messages left for another attempt, per event type");
+    describe_counter!("memo_messages_dead_lettered_total", "Queue messages moved to the failure queue, per event type");
+    describe_histogram!("memo_processor_duration_seconds", Unit::Seconds, "Time spent processing one event");
+    describe_histogram!("memo_dynamodb_duration_seconds", Unit::Seconds, "Latency of store operations against DynamoDB");
+    describe_counter!("memo_dynamodb_errors_total", "Store operations that failed");
+    describe_histogram!("memo_http_request_duration_seconds", Unit::Seconds, "API latency until the response head, per route and status");
+    describe_gauge!("memo_sqs_queue_messages", "ApproximateNumberOfMessages of the event and failure queues");
+
+    Ok(handle)
+}
+
+pub fn count_message(stage: MessageStage, event_type: &str) {
+    counter!(stage.metric_name(), "event_type" => event_type.to_string()).increment(1);
+}
+
+pub fn observe_processor(event_type: &str, outcome: &'static str, elapsed: Duration) {
+    histogram!("memo_processor_duration_seconds", "event_type" => event_type.to_string(), "outcome" => outcome).record(elapsed);
+}
+
+pub fn observe_dynamodb(operation: &'static str, succeeded: bool, elapsed: Duration) {
+    histogram!("memo_dynamodb_duration_seconds", "operation" => operation).record(elapsed);
+    if !succeeded {
+        counter!("memo_dynamodb_errors_total", "operation" => operation).increment(1);
+    }
+}
+
+pub fn observe_http(method: String, route: String, status: u16, elapsed: Duration) {
+    histogram!("memo_http_request_duration_seconds", "method" => method, "route" => route, "status" => status.to_string()).record(elapsed);
+}
+
+pub fn set_queue_depth(queue: &'static str, messages: f64) {
+    gauge!("memo_sqs_queue_messages", "queue" => queue).set(messages);
+}
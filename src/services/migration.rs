@@ -0,0 +1,425 @@
+use std::{collections::HashMap, time::Duration};
+use aws_sdk_dynamodb::{
+    Client as DynamoDbClient, Error as DynamoDbError,
+    error::BuildError,
+    types::{
+        AttributeDefinition, AttributeValue, BillingMode, CreateGlobalSecondaryIndexAction, GlobalSecondaryIndex,
+        GlobalSecondaryIndexUpdate, IndexStatus, KeySchemaElement, KeyType, Projection, ProjectionType,
+        ScalarAttributeType, StreamSpecification, StreamViewType, TableDescription, TableStatus, TimeToLiveSpecification, TimeToLiveStatus,
+    },
+};
+use tokio::time::sleep;
+
+use crate::{
+    adapters::memo_events::processors::model::DBNotifcation,
+    errors::main::{MigrationError, SerializationError, SystemError},
+    services::{
+        keys::{EntityKey, MigrationKey, NotificationKey, SystemKey},
+        retention::RetentionPolicy,
+    },
+};
+
+pub const TTL_ATTRIBUTE: &str = "expires_at";
+const WAIT_ATTEMPTS: usize = 60;
+const WAIT_INTERVAL_SECONDS: u64 = 2;
+
+// Data migrations in the order they run, each one is recorded in the ledger once completed
+#[derive(Debug, Clone, Copy)]
+enum DataMigration {
+    BackfillExpiresAt,
+}
+
+const DATA_MIGRATIONS: [DataMigration; 1] = [DataMigration::BackfillExpiresAt];
+
+impl DataMigration {
+    fn version(&self) -> &'static str {
+        match self {
+            DataMigration::BackfillExpiresAt => "0001_backfill_expires_at",
+        }
+    }
+}
+
+// Billing mode the table is expected to use, PAY_PER_REQUEST or PROVISIONED
+pub fn parse_billing_mode(value: &str) -> Result<BillingMode, SerializationError> {
+    match value {
+        "PAY_PER_REQUEST" => Ok(BillingMode::PayPerRequest),
+        "PROVISIONED" => Ok(BillingMode::Provisioned),
+        _ => Err(SerializationError::new(&format!("invalid billing mode {:?}, expected PAY_PER_REQUEST or PROVISIONED", value))),
+    }
+}
+
+// Creates or verifies the table against the declared schema and runs pending data migrations
+#[derive(Debug)]
+pub struct MigrationService {
+    pub store: DynamoDbClient,
+    pub table_name: String,
+    pub created_time_index: String,
+    pub retention_policy: RetentionPolicy,
+    pub billing_mode: BillingMode,
+    // a table on another billing mode is only reported unless this is set
+    pub update_billing_mode: bool,
+}
+
+// What one page of a data migration did, items it cannot read are skipped and counted
+#[derive(Debug, Default)]
+struct MigrationProgress {
+    updated: i64,
+    skipped: i64,
+}
+
+fn key_schema_element(attribute_name: &str, key_type: KeyType) -> Result<KeySchemaElement, BuildError> {
+    KeySchemaElement::builder()
+        .attribute_name(attribute_name)
+        .key_type(key_type)
+        .build()
+}
+
+fn string_attribute(attribute_name: &str) -> Result<AttributeDefinition, BuildError> {
+    AttributeDefinition::builder()
+        .attribute_name(attribute_name)
+        .attribute_type(ScalarAttributeType::S)
+        .build()
+}
+
+// Change stream read by the SERVER when CHANGE_BUS=DYNAMODB_STREAMS, old images let it tell status changes apart
+fn stream_specification() -> Result<StreamSpecification, BuildError> {
+    StreamSpecification::builder()
+        .stream_enabled(true)
+        .stream_view_type(StreamViewType::NewAndOldImages)
+        .build()
+}
+
+fn has_key(key_schema: &[KeySchemaElement], attribute_name: &str, key_type: KeyType) -> bool {
+    key_schema.iter().any(|key| key.attribute_name() == attribute_name && key.key_type() == &key_type)
+}
+
+impl MigrationService {
+    pub async fn migrate(&self) -> Result<(), SystemError> {
+        self.ensure_table().await?;
+        self.ensure_time_to_live().await?;
+        self.run_data_migrations().await
+    }
+
+    async fn describe_table(&self) -> Result<Option<TableDescription>, SystemError> {
+        let result = self.store.describe_table()
+            .table_name(self.table_name.clone())
+            .send()
+            .await
+            .map_err(DynamoDbError::from);
+        match result {
+            Ok(output) => Ok(output.table),
+            Err(DynamoDbError::ResourceNotFoundException(_)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn wait_until_active(&self) -> Result<TableDescription, SystemError> {
+        for _ in 0..WAIT_ATTEMPTS {
+            if let Some(table) = self.describe_table().await? {
+                let indexes_active = table.global_secondary_indexes().iter()
+                    .all(|index| index.index_status() == Some(&IndexStatus::Active));
+                if table.table_status() == Some(&TableStatus::Active) && indexes_active {
+                    return Ok(table);
+                }
+            }
+            sleep(Duration::from_secs(WAIT_INTERVAL_SECONDS)).await;
+        }
+        Err(MigrationError::new(&format!("table {} did not become active in time", self.table_name)).into())
+    }
+
+    async fn ensure_table(&self) -> Result<(), SystemError> {
+        let table = match self.describe_table().await? {
+            Some(table) => table,
+            None => {
+                if self.billing_mode != BillingMode::PayPerRequest {
+                    return Err(MigrationError::new(&format!("table {} does not exist, a {} table needs its capacity set up before migrating", self.table_name, self.billing_mode.as_str())).into());
+                }
+                tracing::info!("Creating table {}", self.table_name);
+                let index = GlobalSecondaryIndex::builder()
+                    .index_name(self.created_time_index.clone())
+                    .key_schema(key_schema_element("PK", KeyType::Hash).map_err(DynamoDbError::from)?)
+                    .key_schema(key_schema_element("created_time", KeyType::Range).map_err(DynamoDbError::from)?)
+                    .projection(Projection::builder().projection_type(ProjectionType::All).build())
+                    .build()
+                    .map_err(DynamoDbError::from)?;
+                self.store.create_table()
+                    .table_name(self.table_name.clone())
+                    .attribute_definitions(string_attribute("PK").map_err(DynamoDbError::from)?)
+                    .attribute_definitions(string_attribute("SK").map_err(DynamoDbError::from)?)
+                    .attribute_definitions(string_attribute("created_time").map_err(DynamoDbError::from)?)
+                    .key_schema(key_schema_element("PK", KeyType::Hash).map_err(DynamoDbError::from)?)
+                    .key_schema(key_schema_element("SK", KeyType::Range).map_err(DynamoDbError::from)?)
+                    .global_secondary_indexes(index)
+                    .billing_mode(BillingMode::PayPerRequest)
+                    .stream_specification(stream_specification().map_err(DynamoDbError::from)?)
+                    .send()
+                    .await
+                    .map_err(DynamoDbError::from)?;
+                self.wait_until_active().await?;
+                return Ok(());
+            }
+        };
+
+        // the primary key cannot be changed in place, anything else is brought in line
+        if !has_key(table.key_schema(), "PK", KeyType::Hash) || !has_key(table.key_schema(), "SK", KeyType::Range) {
+            return Err(MigrationError::new(&format!("table {} must have PK as hash key and SK as range key", self.table_name)).into());
+        }
+
+        // tables created before billing mode summaries existed are provisioned
+        let billing_mode = table.billing_mode_summary()
+            .and_then(|summary| summary.billing_mode())
+            .unwrap_or(&BillingMode::Provisioned);
+        if billing_mode != &self.billing_mode {
+            if !self.update_billing_mode {
+                tracing::warn!(
+                    "Table {} uses {} billing instead of {}, set MIGRATE_UPDATE_BILLING_MODE=true to switch",
+                    self.table_name, billing_mode.as_str(), self.billing_mode.as_str(),
+                );
+            } else if self.billing_mode == BillingMode::PayPerRequest {
+                tracing::info!("Switching table {} to on-demand billing", self.table_name);
+                self.store.update_table()
+                    .table_name(self.table_name.clone())
+                    .billing_mode(BillingMode::PayPerRequest)
+                    .send()
+                    .await
+                    .map_err(DynamoDbError::from)?;
+                self.wait_until_active().await?;
+            } else {
+                return Err(MigrationError::new(&format!("table {} cannot be switched to provisioned billing without its capacity, change it outside the migration", self.table_name)).into());
+            }
+        }
+
+        match table.stream_specification() {
+            Some(stream) if stream.stream_enabled() => {
+                if stream.stream_view_type() != Some(&StreamViewType::NewAndOldImages) {
+                    return Err(MigrationError::new(&format!("table {} stream must use NEW_AND_OLD_IMAGES", self.table_name)).into());
+                }
+            },
+            _ => {
+                tracing::info!("Enabling the stream on table {}", self.table_name);
+                self.store.update_table()
+                    .table_name(self.table_name.clone())
+                    .stream_specification(stream_specification().map_err(DynamoDbError::from)?)
+                    .send()
+                    .await
+                    .map_err(DynamoDbError::from)?;
+                self.wait_until_active().await?;
+            },
+        }
+
+        let index = table.global_secondary_indexes().iter()
+            .find(|index| index.index_name() == Some(self.created_time_index.as_str()));
+        match index {
+            Some(index) => {
+                if !has_key(index.key_schema(), "PK", KeyType::Hash) || !has_key(index.key_schema(), "created_time", KeyType::Range) {
+                    return Err(MigrationError::new(&format!("index {} must have PK as hash key and created_time as range key", self.created_time_index)).into());
+                }
+            },
+            None => {
+                tracing::info!("Creating index {} on table {}", self.created_time_index, self.table_name);
+                let create_index = CreateGlobalSecondaryIndexAction::builder()
+                    .index_name(self.created_time_index.clone())
+                    .key_schema(key_schema_element("PK", KeyType::Hash).map_err(DynamoDbError::from)?)
+                    .key_schema(key_schema_element("created_time", KeyType::Range).map_err(DynamoDbError::from)?)
+                    .projection(Projection::builder().projection_type(ProjectionType::All).build())
+                    .build()
+                    .map_err(DynamoDbError::from)?;
+                self.store.update_table()
+                    .table_name(self.table_name.clone())
+                    .attribute_definitions(string_attribute("PK").map_err(DynamoDbError::from)?)
+                    .attribute_definitions(string_attribute("created_time").map_err(DynamoDbError::from)?)
+                    .global_secondary_index_updates(GlobalSecondaryIndexUpdate::builder().create(create_index).build())
+                    .send()
+                    .await
+                    .map_err(DynamoDbError::from)?;
+                self.wait_until_active().await?;
+            },
+        }
+
+        tracing::info!("Table {} matches the declared schema", self.table_name);
+        Ok(())
+    }
+
+    async fn ensure_time_to_live(&self) -> Result<(), SystemError> {
+        let result = self.store.describe_time_to_live()
+            .table_name(self.table_name.clone())
+            .send()
+            .await
+            .map_err(DynamoDbError::from)?;
+        let description = result.time_to_live_description();
+        let status = description.and_then(|description| description.time_to_live_status());
+        let attribute_name = description.and_then(|description| description.attribute_name());
+        match status {
+            Some(TimeToLiveStatus::Enabled) | Some(TimeToLiveStatus::Enabling) if attribute_name == Some(TTL_ATTRIBUTE) => {
+                return Ok(());
+            },
+            Some(TimeToLiveStatus::Enabled) | Some(TimeToLiveStatus::Enabling) => {
+                return Err(MigrationError::new(&format!("table {} has TTL on {:?} instead of {}", self.table_name, attribute_name, TTL_ATTRIBUTE)).into());
+            },
+            _ => {},
+        }
+
+        tracing::info!("Enabling TTL on {}.{}", self.table_name, TTL_ATTRIBUTE);
+        let specification = TimeToLiveSpecification::builder()
+            .attribute_name(TTL_ATTRIBUTE)
+            .enabled(true)
+            .build()
+            .map_err(DynamoDbError::from)?;
+        self.store.update_time_to_live()
+            .table_name(self.table_name.clone())
+            .time_to_live_specification(specification)
+            .send()
+            .await
+            .map_err(DynamoDbError::from)?;
+
+        Ok(())
+    }
+
+    fn ledger_key(&self, migration: DataMigration) -> Result<HashMap<String, AttributeValue>, SerializationError> {
+        Ok(HashMap::from([
+            ("PK".to_string(), SystemKey::migrations().attribute()),
+            ("SK".to_string(), MigrationKey::new(migration.version())?.attribute()),
+        ]))
+    }
+
+    async fn run_data_migrations(&self) -> Result<(), SystemError> {
+        for migration in DATA_MIGRATIONS {
+            let ledger = self.store.get_item()
+                .table_name(self.table_name.clone())
+                .set_key(Some(self.ledger_key(migration)?))
+                .consistent_read(true)
+                .send()
+                .await
+                .map_err(DynamoDbError::from)?
+                .item
+                .unwrap_or_default();
+            if ledger.get("status").and_then(|status| status.as_s().ok()).map(String::as_str) == Some("COMPLETED") {
+                tracing::info!("Migration {} already completed", migration.version());
+                continue;
+            }
+
+            // resume from the last recorded page when a previous run was interrupted
+            let mut exclusive_start_key = ledger.get("checkpoint").and_then(|checkpoint| checkpoint.as_m().ok()).cloned();
+            let recorded = |name: &str| ledger.get(name).and_then(|count| count.as_n().ok())
+                .and_then(|count| count.parse::<i64>().ok())
+                .unwrap_or(0);
+            let mut progress = MigrationProgress { updated: recorded("processed"), skipped: recorded("skipped") };
+            tracing::info!("Running migration {} from {} processed items", migration.version(), progress.updated);
+            loop {
+                let page = self.store.scan()
+                    .table_name(self.table_name.clone())
+                    .set_exclusive_start_key(exclusive_start_key)
+                    .send()
+                    .await
+                    .map_err(DynamoDbError::from)?;
+                let page_progress = self.apply(migration, page.items.unwrap_or_default()).await?;
+                progress.updated += page_progress.updated;
+                progress.skipped += page_progress.skipped;
+                exclusive_start_key = page.last_evaluated_key;
+                self.record_progress(migration, &progress, exclusive_start_key.clone()).await?;
+                if exclusive_start_key.is_none() {
+                    break;
+                }
+            }
+            if progress.skipped > 0 {
+                tracing::warn!("Migration {} skipped {} items it could not read", migration.version(), progress.skipped);
+            }
+            tracing::info!("Migration {} completed, {} items updated", migration.version(), progress.updated);
+        }
+
+        Ok(())
+    }
+
+    async fn record_progress(&self, migration: DataMigration, progress: &MigrationProgress, checkpoint: Option<HashMap<String, AttributeValue>>) -> Result<(), SystemError> {
+        let mut ledger = self.ledger_key(migration)?;
+        ledger.insert("processed".to_string(), AttributeValue::N(progress.updated.to_string()));
+        ledger.insert("skipped".to_string(), AttributeValue::N(progress.skipped.to_string()));
+        ledger.insert("updated_time".to_string(), AttributeValue::S(chrono::Utc::now().to_rfc3339()));
+        match checkpoint {
+            Some(checkpoint) => {
+                ledger.insert("status".to_string(), AttributeValue::S("RUNNING".to_string()));
+                ledger.insert("checkpoint".to_string(), AttributeValue::M(checkpoint));
+            },
+            None => {
+                ledger.insert("status".to_string(), AttributeValue::S("COMPLETED".to_string()));
+            },
+        }
+        self.store.put_item()
+            .table_name(self.table_name.clone())
+            .set_item(Some(ledger))
+            .send()
+            .await
+            .map_err(DynamoDbError::from)?;
+
+        Ok(())
+    }
+
+    async fn apply(&self, migration: DataMigration, items: Vec<HashMap<String, AttributeValue>>) -> Result<MigrationProgress, SystemError> {
+        match migration {
+            DataMigration::BackfillExpiresAt => self.backfill_expires_at(items).await,
+        }
+    }
+
+    // Notifications written before retention existed get the TTL their current status implies
+    async fn backfill_expires_at(&self, items: Vec<HashMap<String, AttributeValue>>) -> Result<MigrationProgress, SystemError> {
+        let mut progress = MigrationProgress::default();
+        let notification_prefix = NotificationKey::key_prefix();
+        for item in items {
+            let is_notification = item.get("SK").and_then(|sk| sk.as_s().ok())
+                .map(|sk| sk.starts_with(&notification_prefix))
+                .unwrap_or(false);
+            if !is_notification || item.get(TTL_ATTRIBUTE).map(|ttl| ttl.is_n()).unwrap_or(false) {
+                continue;
+            }
+            let key = HashMap::from([
+                ("PK".to_string(), item["PK"].clone()),
+                ("SK".to_string(), item["SK"].clone()),
+            ]);
+            let notif = match DBNotifcation::from_item(item) {
+                Ok(notif) => notif,
+                Err(e) => {
+                    tracing::warn!("skip undecodable notification {:?}: {}", key, e);
+                    progress.skipped += 1;
+                    continue;
+                },
+            };
+            let since = notif.updated_time.as_ref().unwrap_or(&notif.created_time);
+            let since = match chrono::DateTime::parse_from_rfc3339(since) {
+                Ok(since) => since.with_timezone(&chrono::Utc),
+                Err(e) => {
+                    tracing::warn!("skip notification {:?} with invalid time {:?}: {}", key, since, e);
+                    progress.skipped += 1;
+                    continue;
+                },
+            };
+            let expires_at = match self.retention_policy.expires_at(&notif.notification_type, &notif.status, since) {
+                Some(expires_at) => expires_at,
+                None => continue,
+            };
+
+            let result = self.store.update_item()
+                .table_name(self.table_name.clone())
+                .set_key(Some(key))
+                // like any other write, so an ETag taken before the backfill no longer matches
+                .update_expression("SET #expires_at = :expires_at, #version = if_not_exists(#version, :zero) + :one")
+                .condition_expression("attribute_exists(SK) AND (attribute_not_exists(#expires_at) OR attribute_type(#expires_at, :null_type))")
+                .expression_attribute_names("#expires_at", TTL_ATTRIBUTE)
+                .expression_attribute_names("#version", "version")
+                .expression_attribute_values(":expires_at", AttributeValue::N(expires_at.to_string()))
+                .expression_attribute_values(":null_type", AttributeValue::S("NULL".to_string()))
+                .expression_attribute_values(":zero", AttributeValue::N("0".to_string()))
+                .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+                .send()
+                .await
+                .map_err(DynamoDbError::from);
+            match result {
+                Ok(_) => progress.updated += 1,
+                // changed or deleted since the scan, whoever wrote it set the TTL already
+                Err(DynamoDbError::ConditionalCheckFailedException(_)) => {},
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(progress)
+    }
+}
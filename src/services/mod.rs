@@ -1,2 +1,13 @@
 pub mod store;
-pub mod notification;
\ No newline at end of file
+pub mod notification;
+pub mod retention;
+pub mod archive;
+pub mod keys;
+pub mod migration;
+pub mod bus;
+pub mod cache;
+pub mod outbox;
+pub mod hub;
+pub mod health;
+pub mod metrics;
+pub mod measured_store;
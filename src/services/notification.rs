@@ -1,72 +1,298 @@
 use async_trait::async_trait;
-use serde_dynamo::from_items;
+use aws_sdk_dynamodb::{Error as DynamoDbError, types::AttributeValue};
+use std::{collections::{HashMap, HashSet}, sync::Arc};
+use ulid::Ulid;
 
 use crate::{
-  utils::utils::struct_to_hashmap,
-  adapters::{memo_events::processors::{model::{CreateMessageBody, DBNotifcation, NotificationType, NotificationStatus},
-  event_type_processor::{PermanentError, ApplicationError, RetryableError}}, memo_api::router::UpdateNotificationBody},
-  services::store::{DatabaseStoreService, DatabaseStoreInterface}
+  client::eventbridge_client::{EventPublisherInterface, PublishEvent},
+  utils::utils::from_attribute_map,
+  adapters::{memo_events::processors::{model::{CreateMessageBody, DBNotifcation, DBOutboxEvent, DBStatusChange, DBUnreadCounter, NotificationType, NotificationStatus, BulkUpdateOutcome, BulkUpdateResult},
+  event_type_processor::{PermanentError, ApplicationError, RetryableError, ConflictError, NotFoundError, PreconditionFailedError}}, memo_api::router::{UpdateNotificationBody, BulkUpdateNotificationBody}},
+  services::{store::{DatabaseStoreInterface, StatusTransition, StatusChange, SourceMapping, BatchCreate, BULK_UPDATE_CHUNK_SIZE}, retention::RetentionPolicy, archive::ArchiveSinkInterface,
+  outbox::{NotificationCreatedDetail, StatusChangedDetail, status_changed_detail_type, EVENT_SOURCE, NOTIFICATION_CREATED},
+  keys::{EntityKey, UserKey, NotificationKey, SourceNotificationKey, OutboxKey, EventKey, SubscriptionKey, NotificationIdMode}, bus::{NotificationEvent, SharedNotificationBus}}
 };
 
+const UPDATE_MAX_ATTEMPTS: usize = 3;
+const RECONCILE_MAX_ATTEMPTS: usize = 3;
+const OUTBOX_RELAY_BATCH_SIZE: i32 = 10;
+
 // Define the trait for database operations
 #[async_trait]
 pub trait NotificationServiceInterface {
   async fn create_notification_message(&self, body: CreateMessageBody) -> Result<(), ApplicationError>;
-  async fn get_notification_by_user_id(&self, user_id: String) -> Result<Vec<DBNotifcation>, ApplicationError>;
-  async fn update_notification_message(&self, user_id: String, noti_id: String, payload: UpdateNotificationBody) -> Result<(), ApplicationError>;
+  async fn create_fan_out_notification_messages(&self, body: CreateMessageBody) -> Result<usize, ApplicationError>;
+  async fn get_notification_by_user_id(&self, user_key: UserKey) -> Result<Vec<DBNotifcation>, ApplicationError>;
+  async fn update_notification_message(&self, user_key: UserKey, noti_key: NotificationKey, payload: UpdateNotificationBody) -> Result<DBNotifcation, ApplicationError>;
+  async fn get_unread_count(&self, user_key: UserKey) -> Result<i64, ApplicationError>;
+  async fn reconcile_unread_counts(&self) -> Result<usize, ApplicationError>;
+  async fn bulk_update_notification_messages(&self, user_key: UserKey, payload: BulkUpdateNotificationBody) -> Result<Vec<BulkUpdateOutcome>, ApplicationError>;
+  async fn archive_expiring_notifications(&self, sink: &(dyn ArchiveSinkInterface + Send + Sync), horizon: i64) -> Result<usize, ApplicationError>;
+  async fn relay_outbox_events(&self, publisher: &(dyn EventPublisherInterface + Send + Sync)) -> Result<usize, ApplicationError>;
+  async fn subscribe_topic(&self, user_key: UserKey, topic_id: String) -> Result<(), ApplicationError>;
+  async fn get_topic_subscriptions(&self, user_key: UserKey) -> Result<Vec<String>, ApplicationError>;
+  async fn may_follow_topic(&self, user_key: UserKey, topic_id: String) -> Result<bool, ApplicationError>;
 }
 
+// Handle shared by every adapter, so the implementation can be swapped for a fake
+pub type SharedNotificationService = Arc<dyn NotificationServiceInterface + Send + Sync>;
+
 // Define the struct implementing the trait
-#[derive(Debug)]
 pub struct NotificationService {
-  pub database_store_service: DatabaseStoreService,
+  pub database_store_service: Arc<dyn DatabaseStoreInterface + Send + Sync>,
+  pub retention_policy: RetentionPolicy,
+  pub notification_id_mode: NotificationIdMode,
+  pub notification_bus: SharedNotificationBus,
 }
 
-#[async_trait]
-impl NotificationServiceInterface for NotificationService {
-  async fn update_notification_message(&self, user_id: String, noti_id: String, payload: UpdateNotificationBody) -> Result<(), ApplicationError> {
-    let noti_output  = self.database_store_service
-      .db_get_notification_item_with_pk_sk(
-        format!("USR#{}", user_id),
-        format!("NTF#{}", noti_id)
-      )
+impl NotificationService {
+  async fn stored_unread_count(&self, user_key: UserKey) -> Result<i64, ApplicationError> {
+    let result = self.database_store_service
+      .db_get_unread_count(user_key)
       .await.map_err(|e| RetryableError::new(&e.to_string()))?;
 
-    if let Some(_) = noti_output.item {
-        self.database_store_service
-          .db_update_notification_message(
-            user_id,
-            noti_id,
-            payload.action,
-          )
-          .await.map_err(|e| RetryableError::new(&e.to_string()))?;
-    } else {
-        println!("update notification item not found");
+    match result.item {
+      Some(item) => {
+        let counter: DBUnreadCounter = from_attribute_map(item).map_err(|e| PermanentError::new(&e.to_string()))?;
+        Ok(counter.unread_count)
+      },
+      None => Ok(0),
     }
+  }
 
-    Ok(())
+  // Recounts one user's unread notifications, true when the stored counter had drifted.
+  // The counter is read before counting and only overwritten if it still holds that value,
+  // a write that lands in between moves the counter and the count is taken again.
+  // Changes still pending on a notification are completed first, their counter update is not in yet
+  async fn reconcile_unread_count(&self, user_key: &UserKey) -> Result<bool, ApplicationError> {
+    for _ in 0..RECONCILE_MAX_ATTEMPTS {
+      let stored = self.stored_unread_count(user_key.clone()).await?;
+      let items = self.database_store_service
+        .db_get_notification_states(user_key.clone())
+        .await.map_err(|e| RetryableError::new(&e.to_string()))?;
+      let unread_status = format!("{:?}", NotificationStatus::UNREAD);
+      let mut actual = 0;
+      let mut pending = vec![];
+      for item in items {
+        if item.get("status").and_then(|status| status.as_s().ok()) == Some(&unread_status) {
+          actual += 1;
+        }
+        if let Some(change) = DBStatusChange::from_item(&item).map_err(|e| PermanentError::new(&e.to_string()))? {
+          let sk = item.get("SK").and_then(|sk| sk.as_s().ok()).map(String::as_str).unwrap_or_default();
+          pending.push((NotificationKey::decode(sk).map_err(|e| PermanentError::new(&e.to_string()))?, change));
+        }
+      }
+      if !pending.is_empty() {
+        for (noti_key, change) in pending {
+          tracing::info!("complete pending status change {} of notification {}", change.event_id, noti_key.id());
+          self.complete_status_change(user_key, &noti_key, change).await?;
+        }
+        continue;
+      }
+      if actual == stored {
+        return Ok(false);
+      }
+      match self.database_store_service.db_set_unread_count(user_key.clone(), stored, actual).await {
+        Ok(()) => {
+          tracing::info!("reconcile unread count for user {}: stored {} actual {}", user_key.id(), stored, actual);
+          return Ok(true);
+        },
+        Err(DynamoDbError::ConditionalCheckFailedException(_)) => continue,
+        Err(e) => return Err(RetryableError::new(&e.to_string()).into()),
+      }
+    }
+    // the user is busy, the next run gets another chance
+    tracing::warn!("reconcile unread count for user {}: counter kept changing, skipped", user_key.id());
+    Ok(false)
   }
 
-  async fn get_notification_by_user_id(&self, user_id: String) -> Result<Vec<DBNotifcation>, ApplicationError> {
-    // Implementation for getting a notification message from DynamoDB
-    let mut noti_items: Vec<DBNotifcation> = vec![];
-    let result = self.database_store_service
-      .db_get_notifications_by_user_id(user_id)
-      .await.map_err(|e| RetryableError::new(&e.to_string()))?;
+  // Narrows one read of notifications down to the ones a bulk update applies to
+  fn bulk_update_targets(payload: &BulkUpdateNotificationBody, mut targets: Vec<DBNotifcation>) -> Vec<DBNotifcation> {
+    if let Some(before) = payload.before {
+      targets.retain(|notif| {
+        chrono::DateTime::parse_from_rfc3339(&notif.created_time)
+          .map(|created_time| created_time < before)
+          .unwrap_or(false)
+      });
+    }
+    if payload.ids.is_none() {
+      // "mark all" only touches notifications that can legally reach the requested status
+      targets.retain(|notif| notif.status == payload.action || notif.status.can_transition_to(&payload.action));
+    }
+    targets
+  }
 
-    if let Some(items) = result.items {
-      noti_items = from_items(items).map_err(|e| PermanentError::new(&e.to_string()))?;
-      println!("Got {} notification", noti_items.len());
+  // Updates the targets of a bulk update chunk by chunk, recording each one's result
+  async fn apply_bulk_update(&self, user_key: &UserKey, action: &NotificationStatus, targets: Vec<DBNotifcation>, results: &mut HashMap<String, BulkUpdateResult>) -> Result<(), ApplicationError> {
+    let mut pending: Vec<(NotificationKey, StatusTransition)> = vec![];
+    let now = chrono::Utc::now();
+    for notif in targets {
+      if notif.status == *action {
+        results.insert(notif.notification_id, BulkUpdateResult::Unchanged);
+      } else if !notif.status.can_transition_to(action) {
+        results.insert(notif.notification_id, BulkUpdateResult::Conflict);
+      } else {
+        let mut transition = StatusTransition {
+          expires_at: self.retention_policy.expires_at(&notif.notification_type, action, now),
+          notification_type: notif.notification_type,
+          from_status: notif.status,
+          status: action.clone(),
+          updated_time: now.to_rfc3339(),
+          expected_version: None,
+          outbox_item: None,
+        };
+        let noti_key = NotificationKey::new(notif.notification_id).map_err(|e| PermanentError::new(&e.to_string()))?;
+        let change = DBStatusChange {
+          event_id: Ulid::new().to_string(),
+          from_status: transition.from_status.clone(),
+          status: transition.status.clone(),
+          updated_time: transition.updated_time.clone(),
+        };
+        transition.outbox_item = Some(self.status_changed_event(user_key, &noti_key, &change)?);
+        pending.push((noti_key, transition));
+      }
     }
 
-    Ok(noti_items)
+    for chunk in pending.chunks(BULK_UPDATE_CHUNK_SIZE) {
+      let mut updates = chunk.to_vec();
+      // a cancelled transaction is retried once without the items whose status moved underneath us
+      for attempt in 0..2 {
+        let result = self.database_store_service
+          .db_bulk_update_notification_messages(user_key.clone(), updates.clone())
+          .await;
+        match result {
+          Ok(_) => {
+            for (noti_key, transition) in updates.drain(..) {
+              self.notification_bus.publish(NotificationEvent::StatusChanged {
+                user_id: user_key.id().to_string(),
+                notification_id: noti_key.id().to_string(),
+                status: transition.status,
+              }).await;
+              results.insert(noti_key.id().to_string(), BulkUpdateResult::Updated);
+            }
+            break;
+          },
+          Err(DynamoDbError::TransactionCanceledException(e)) if attempt == 0 => {
+            tracing::error!("bulk_update_notification_messages transaction cancelled: {:?}", e);
+            let reasons = e.cancellation_reasons().to_vec();
+            let mut retry = vec![];
+            let mut failed_checks = vec![];
+            for (index, update) in updates.drain(..).enumerate() {
+              let code = reasons.get(index).and_then(|reason| reason.code()).unwrap_or("None");
+              if code == "ConditionalCheckFailed" {
+                failed_checks.push(update.0);
+              } else {
+                retry.push(update);
+              }
+            }
+            self.record_failed_checks(user_key, failed_checks, results).await;
+            updates = retry;
+            if updates.is_empty() {
+              break;
+            }
+          },
+          Err(e) => {
+            tracing::error!("bulk_update_notification_messages Error: {:?}", e);
+            for (noti_key, _) in updates.drain(..) {
+              results.insert(noti_key.id().to_string(), BulkUpdateResult::Failed);
+            }
+            break;
+          },
+        }
+      }
+    }
+    Ok(())
   }
 
-  async fn create_notification_message(&self, body: CreateMessageBody) -> Result<(), ApplicationError> {
-    let now = chrono::Utc::now().to_rfc3339();
+  // A failed condition is a conflict when the notification changed and not found when it was deleted
+  // since it was read, so the items are read again to tell which
+  async fn record_failed_checks(&self, user_key: &UserKey, noti_keys: Vec<NotificationKey>, results: &mut HashMap<String, BulkUpdateResult>) {
+    if noti_keys.is_empty() {
+      return;
+    }
+    let existing: HashSet<String> = match self.database_store_service.db_batch_get_notifications(user_key.clone(), noti_keys.clone()).await {
+      Ok(items) => items.iter()
+        .filter_map(|item| item.get("SK").and_then(|sk| sk.as_s().ok()))
+        .filter_map(|sk| NotificationKey::decode(sk).ok())
+        .map(|noti_key| noti_key.id().to_string())
+        .collect(),
+      Err(e) => {
+        // without the read every failed condition stays a conflict
+        tracing::error!("bulk_update_notification_messages re-read Error: {:?}", e);
+        noti_keys.iter().map(|noti_key| noti_key.id().to_string()).collect()
+      },
+    };
+    for noti_key in noti_keys {
+      let result = match existing.contains(noti_key.id()) {
+        true => BulkUpdateResult::Conflict,
+        false => BulkUpdateResult::NotFound,
+      };
+      results.insert(noti_key.id().to_string(), result);
+    }
+  }
+
+  fn created_event(&self, user_key: &UserKey, notif: &DBNotifcation) -> Result<HashMap<String, AttributeValue>, ApplicationError> {
+    let detail = NotificationCreatedDetail { notification: notif };
+    let event = DBOutboxEvent::new(user_key, EVENT_SOURCE, NOTIFICATION_CREATED, &detail).map_err(|e| PermanentError::new(&e.to_string()))?;
+    Ok(event.to_item().map_err(|e| PermanentError::new(&e.to_string()))?)
+  }
+
+  // Outbox item announcing a status change, its id is the one the change carries
+  fn status_changed_event(&self, user_key: &UserKey, noti_key: &NotificationKey, change: &DBStatusChange) -> Result<HashMap<String, AttributeValue>, ApplicationError> {
+    let detail = StatusChangedDetail {
+      user_id: user_key.id(),
+      notification_id: noti_key.id(),
+      status: &change.status,
+      previous_status: &change.from_status,
+      updated_time: &change.updated_time,
+    };
+    let event_key = EventKey::new(change.event_id.clone()).map_err(|e| PermanentError::new(&e.to_string()))?;
+    let event = DBOutboxEvent::with_key(&event_key, user_key, EVENT_SOURCE, status_changed_detail_type(&change.status), &detail).map_err(|e| PermanentError::new(&e.to_string()))?;
+    Ok(event.to_item().map_err(|e| PermanentError::new(&e.to_string()))?)
+  }
+
+  // Writes the outbox event and counter update of a change left pending on the notification.
+  // Whoever clears the marker first does it, a writer that finds it gone has nothing left to do
+  async fn complete_status_change(&self, user_key: &UserKey, noti_key: &NotificationKey, change: DBStatusChange) -> Result<(), ApplicationError> {
+    let outbox_item = self.status_changed_event(user_key, noti_key, &change)?;
+    let result = self.database_store_service
+      .db_complete_status_change(user_key.clone(), noti_key.clone(), change.clone(), outbox_item)
+      .await;
+    match result {
+      Ok(()) => {
+        self.notification_bus.publish(NotificationEvent::StatusChanged {
+          user_id: user_key.id().to_string(),
+          notification_id: noti_key.id().to_string(),
+          status: change.status,
+        }).await;
+        Ok(())
+      },
+      Err(DynamoDbError::TransactionCanceledException(e))
+        if e.cancellation_reasons().first().and_then(|reason| reason.code()) == Some("ConditionalCheckFailed") => Ok(()),
+      Err(e) => Err(RetryableError::new(&e.to_string()).into()),
+    }
+  }
+
+  // Builds the UNREAD item for one recipient, with the upstream id mapping when ids are generated
+  fn new_notification(&self, body: &CreateMessageBody, user_id: &str, created_at: chrono::DateTime<chrono::Utc>) -> Result<(DBNotifcation, Option<SourceMapping>), ApplicationError> {
+    let now = created_at.to_rfc3339();
+    let expires_at = self.retention_policy.expires_at(&NotificationType::Message, &NotificationStatus::UNREAD, created_at);
+    let (notification_id, source) = match self.notification_id_mode {
+      NotificationIdMode::Upstream => (body.detail.notification_id.clone(), None),
+      NotificationIdMode::Ulid => {
+        let notification_id = Ulid::from_datetime(created_at.into()).to_string();
+        let source = SourceMapping {
+          source_key: SourceNotificationKey::new(body.detail.notification_id.clone()).map_err(|e| PermanentError::new(&e.to_string()))?,
+          notification_key: NotificationKey::new(notification_id.clone()).map_err(|e| PermanentError::new(&e.to_string()))?,
+          expires_at,
+        };
+        (notification_id, Some(source))
+      },
+    };
+    let source_notification_id = source.as_ref().map(|_| body.detail.notification_id.clone());
     let d = DBNotifcation{
-      notification_id: format!("NTF#{}",body.detail.notification_id.clone()),
-      user_id: format!("USR#{}", body.detail.user_id.clone()),
+      notification_id,
+      user_id: user_id.to_string(),
       replyer_id: body.detail.replyer_id.clone(),
       replyer_avatar: body.detail.replyer_avatar.clone(),
       replyer_name: body.detail.replyer_name.clone(),
@@ -75,19 +301,348 @@ impl NotificationServiceInterface for NotificationService {
       topic_id: body.detail.topic_id.clone(),
       message_id: body.detail.message_id.clone(),
       content: body.detail.content.clone(),
+      metadata: body.detail.metadata.clone(),
+      source_notification_id,
       created_time: now,
       updated_time: None,
+      read_at: None,
+      removed_at: None,
+      expires_at,
+      version: 1,
     };
 
+    Ok((d, source))
+  }
+}
+
+#[async_trait]
+impl NotificationServiceInterface for NotificationService {
+  async fn update_notification_message(&self, user_key: UserKey, noti_key: NotificationKey, payload: UpdateNotificationBody) -> Result<DBNotifcation, ApplicationError> {
+    let now = chrono::Utc::now();
+    // the only notification type so far, a failed condition tells us if the item has another one
+    let mut notification_type = NotificationType::Message;
+    for _ in 0..UPDATE_MAX_ATTEMPTS {
+      let change = StatusChange {
+        expires_at: self.retention_policy.expires_at(&notification_type, &payload.action, now),
+        notification_type: notification_type.clone(),
+        status: payload.action.clone(),
+        updated_time: now.to_rfc3339(),
+        expected_version: payload.expected_version,
+        event_key: EventKey::new(Ulid::new().to_string()).map_err(|e| PermanentError::new(&e.to_string()))?,
+      };
+      let result = self.database_store_service
+        .db_update_notification_status(user_key.clone(), noti_key.clone(), change)
+        .await;
+      let current = match result {
+        Ok(item) => {
+          let notif = DBNotifcation::from_item(item.clone()).map_err(|e| PermanentError::new(&e.to_string()))?;
+          if let Some(change) = DBStatusChange::from_item(&item).map_err(|e| PermanentError::new(&e.to_string()))? {
+            // the status is changed already, the reconciler writes the event if this fails
+            if let Err(e) = self.complete_status_change(&user_key, &noti_key, change).await {
+              tracing::error!("complete status change of notification {} Error: {:?}", noti_key.id(), e);
+            }
+          }
+          return Ok(notif);
+        },
+        Err(DynamoDbError::ConditionalCheckFailedException(e)) => match e.item {
+          Some(item) => {
+            // an earlier change still waits for its event, finish it and try again
+            if let Some(change) = DBStatusChange::from_item(&item).map_err(|e| PermanentError::new(&e.to_string()))? {
+              self.complete_status_change(&user_key, &noti_key, change).await?;
+              continue;
+            }
+            DBNotifcation::from_item(item).map_err(|e| PermanentError::new(&e.to_string()))?
+          },
+          None => return Err(NotFoundError::new(&format!("notification {} not found", noti_key.id())).into()),
+        },
+        Err(e) => return Err(RetryableError::new(&e.to_string()).into()),
+      };
+
+      if let Some(expected_version) = payload.expected_version {
+        if current.version != expected_version {
+          return Err(PreconditionFailedError::new(&format!("notification {} is at version {}, not {}", noti_key.id(), current.version, expected_version)).into());
+        }
+      }
+      if current.status == payload.action {
+        return Ok(current);
+      }
+      if !current.status.can_transition_to(&payload.action) {
+        return Err(ConflictError::new(&format!("cannot change notification status from {:?} to {:?}", current.status, payload.action)).into());
+      }
+      notification_type = current.notification_type;
+    }
+
+    Err(ConflictError::new("notification changed concurrently, please retry").into())
+  }
+
+  async fn get_notification_by_user_id(&self, user_key: UserKey) -> Result<Vec<DBNotifcation>, ApplicationError> {
+    // Implementation for getting a notification message from DynamoDB
+    let mut noti_items: Vec<DBNotifcation> = vec![];
+    let result = self.database_store_service
+      .db_get_notifications_by_user_id(user_key)
+      .await.map_err(|e| RetryableError::new(&e.to_string()))?;
+
+    if let Some(items) = result.items {
+      noti_items = DBNotifcation::from_items(items).map_err(|e| PermanentError::new(&e.to_string()))?;
+      tracing::debug!("Got {} notifications", noti_items.len());
+    }
+
+    Ok(noti_items)
+  }
+
+  async fn create_notification_message(&self, body: CreateMessageBody) -> Result<(), ApplicationError> {
+    let user_key = UserKey::new(body.detail.user_id.clone()).map_err(|e| PermanentError::new(&e.to_string()))?;
+    let (d, source) = self.new_notification(&body, &body.detail.user_id, chrono::Utc::now())?;
+
     let condition_expression = "attribute_not_exists(PK) AND attribute_not_exists(SK)";
 
-    let item = struct_to_hashmap(&d)
-            .map_err(|_| PermanentError::new("Failed to convert struct to hashmap"))?;
+    let item = d.to_item()
+            .map_err(|e| PermanentError::new(&e.to_string()))?;
+
+    let outbox_item = self.created_event(&user_key, &d)?;
+
+    let result = self.database_store_service
+      .db_create_notification_message(user_key, item, condition_expression.to_string(), source, outbox_item)
+      .await;
+    match result {
+      Ok(_) => {
+        self.notification_bus.publish(NotificationEvent::Created(Box::new(d))).await;
+        Ok(())
+      },
+      // the notification or its upstream id mapping already exists, the event is a replay
+      Err(DynamoDbError::TransactionCanceledException(e))
+        if e.cancellation_reasons().iter().any(|reason| reason.code() == Some("ConditionalCheckFailed")) => {
+        tracing::info!("notification {} already created, skipping duplicate event", body.detail.notification_id);
+        Ok(())
+      },
+      Err(e) => Err(RetryableError::new(&e.to_string()).into()),
+    }
+  }
+
+  async fn create_fan_out_notification_messages(&self, body: CreateMessageBody) -> Result<usize, ApplicationError> {
+    let created_at = chrono::Utc::now();
+    let mut recipient_ids = body.detail.recipient_ids.clone().unwrap_or_default();
+    recipient_ids.sort();
+    recipient_ids.dedup();
+
+    let mut creates = vec![];
+    let mut notifications: HashMap<UserKey, DBNotifcation> = HashMap::new();
+    for recipient_id in recipient_ids.iter() {
+      let user_key = UserKey::new(recipient_id.clone()).map_err(|e| PermanentError::new(&e.to_string()))?;
+      let (d, source) = self.new_notification(&body, recipient_id, created_at)?;
+      let item = d.to_item().map_err(|e| PermanentError::new(&e.to_string()))?;
+      let outbox_item = self.created_event(&user_key, &d)?;
+      creates.push(BatchCreate { user_key: user_key.clone(), item, source, outbox_item });
+      notifications.insert(user_key, d);
+    }
+
+    // recipients that already have the notification are skipped, so a replayed event only fills the gaps
+    let batch = self.database_store_service
+      .db_batch_create_notification_messages(creates)
+      .await;
+    tracing::info!("notification {} fanned out to {} of {} recipients", body.detail.notification_id, batch.created.len(), recipient_ids.len());
+    for user_key in batch.created.iter() {
+      if let Some(d) = notifications.remove(user_key) {
+        self.notification_bus.publish(NotificationEvent::Created(Box::new(d))).await;
+      }
+    }
+
+    // the created ones are kept, a redelivery of the event retries only the failed recipients
+    if let Some((user_key, e)) = batch.failed.first() {
+      tracing::warn!("notification {} failed for {} recipients, first {}: {}", body.detail.notification_id, batch.failed.len(), user_key.id(), e);
+      return Err(RetryableError::new(&format!("fan-out failed for {} of {} recipients: {}", batch.failed.len(), recipient_ids.len(), e)).into());
+    }
+
+    Ok(batch.created.len())
+  }
+
+  async fn get_unread_count(&self, user_key: UserKey) -> Result<i64, ApplicationError> {
+    // a drifted counter is fixed by the reconciler, never show a negative badge meanwhile
+    Ok(self.stored_unread_count(user_key).await?.max(0))
+  }
+
+  async fn reconcile_unread_counts(&self) -> Result<usize, ApplicationError> {
+    let mut reconciled = 0;
+    let mut seen_users: HashSet<UserKey> = HashSet::new();
+    let mut exclusive_start_key = None;
+    loop {
+      let page = self.database_store_service
+        .db_scan_user_partitions(exclusive_start_key)
+        .await.map_err(|e| RetryableError::new(&e.to_string()))?;
+
+      for item in page.items.unwrap_or_default() {
+        let user_key = match item.get("PK").and_then(|pk| pk.as_s().ok()).map(|pk| UserKey::decode(pk)) {
+          Some(Ok(user_key)) => user_key,
+          _ => continue,
+        };
+        if !seen_users.insert(user_key.clone()) {
+          continue;
+        }
+
+        if self.reconcile_unread_count(&user_key).await? {
+          self.notification_bus.publish(NotificationEvent::CountChanged { user_id: user_key.id().to_string() }).await;
+          reconciled += 1;
+        }
+      }
+
+      exclusive_start_key = page.last_evaluated_key;
+      if exclusive_start_key.is_none() {
+        break;
+      }
+    }
+
+    Ok(reconciled)
+  }
+
+  async fn bulk_update_notification_messages(&self, user_key: UserKey, mut payload: BulkUpdateNotificationBody) -> Result<Vec<BulkUpdateOutcome>, ApplicationError> {
+    // a repeated id is updated and reported once, DynamoDB rejects batches that name an item twice
+    if let Some(ids) = payload.ids.as_mut() {
+      let mut seen = HashSet::new();
+      ids.retain(|noti_key| seen.insert(noti_key.clone()));
+    }
+    let mut results: HashMap<String, BulkUpdateResult> = HashMap::new();
+    if let Some(ids) = &payload.ids {
+      let items = self.database_store_service
+        .db_batch_get_notifications(user_key.clone(), ids.clone())
+        .await.map_err(|e| RetryableError::new(&e.to_string()))?;
+      let notifications = DBNotifcation::from_items(items).map_err(|e| PermanentError::new(&e.to_string()))?;
+      self.apply_bulk_update(&user_key, &payload.action, Self::bulk_update_targets(&payload, notifications), &mut results).await?;
+    } else {
+      // "mark all" goes through the partition a page at a time, only the results are kept
+      let mut exclusive_start_key = None;
+      loop {
+        let page = self.database_store_service
+          .db_get_all_notifications_by_user_id(user_key.clone(), exclusive_start_key)
+          .await.map_err(|e| RetryableError::new(&e.to_string()))?;
+        let notifications = DBNotifcation::from_items(page.items.unwrap_or_default()).map_err(|e| PermanentError::new(&e.to_string()))?;
+        self.apply_bulk_update(&user_key, &payload.action, Self::bulk_update_targets(&payload, notifications), &mut results).await?;
+        exclusive_start_key = page.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+          break;
+        }
+      }
+    }
+
+    // requested ids that were never found keep their position in the response
+    let mut outcomes = vec![];
+    if let Some(ids) = payload.ids {
+      for noti_key in ids {
+        let result = results.remove(noti_key.id()).unwrap_or(BulkUpdateResult::NotFound);
+        outcomes.push(BulkUpdateOutcome { notification_id: noti_key.id().to_string(), result });
+      }
+    } else {
+      for (notification_id, result) in results {
+        outcomes.push(BulkUpdateOutcome { notification_id, result });
+      }
+      outcomes.sort_by(|a, b| a.notification_id.cmp(&b.notification_id));
+    }
+
+    Ok(outcomes)
+  }
+
+  async fn archive_expiring_notifications(&self, sink: &(dyn ArchiveSinkInterface + Send + Sync), horizon: i64) -> Result<usize, ApplicationError> {
+    let mut archived = 0;
+    let mut exclusive_start_key = None;
+    loop {
+      let page = self.database_store_service
+        .db_scan_expiring_notifications(horizon, exclusive_start_key)
+        .await.map_err(|e| RetryableError::new(&e.to_string()))?;
+      let items = DBNotifcation::from_items(page.items.unwrap_or_default()).map_err(|e| PermanentError::new(&e.to_string()))?;
+
+      // written to the sink first, a crash in between archives an item twice rather than losing it.
+      // The item itself is left to the DynamoDB TTL, so nothing is removed before it expires
+      sink.archive(&items).await?;
+      let archived_at = chrono::Utc::now().timestamp();
+      for notif in items {
+        let user_key = UserKey::new(notif.user_id.clone()).map_err(|e| PermanentError::new(&e.to_string()))?;
+        let noti_key = NotificationKey::new(notif.notification_id.clone()).map_err(|e| PermanentError::new(&e.to_string()))?;
+        let result = self.database_store_service
+          .db_mark_notification_archived(user_key, noti_key, notif.version, archived_at)
+          .await;
+        match result {
+          Ok(_) => archived += 1,
+          // changed after the scan, the next run archives the new state
+          Err(DynamoDbError::ConditionalCheckFailedException(_)) => {
+            tracing::info!("skip marking notification {} changed since scan as archived", notif.notification_id);
+          },
+          Err(e) => return Err(RetryableError::new(&e.to_string()).into()),
+        }
+      }
+
+      exclusive_start_key = page.last_evaluated_key;
+      if exclusive_start_key.is_none() {
+        break;
+      }
+    }
+
+    Ok(archived)
+  }
+
+  async fn relay_outbox_events(&self, publisher: &(dyn EventPublisherInterface + Send + Sync)) -> Result<usize, ApplicationError> {
+    let mut relayed = 0;
+    for outbox_key in OutboxKey::all() {
+      loop {
+        let page = self.database_store_service
+          .db_get_outbox_events(outbox_key.clone(), OUTBOX_RELAY_BATCH_SIZE)
+          .await.map_err(|e| RetryableError::new(&e.to_string()))?;
+        let events = DBOutboxEvent::from_items(page.items.unwrap_or_default()).map_err(|e| PermanentError::new(&e.to_string()))?;
+        if events.is_empty() {
+          break;
+        }
+
+        // published before deleting, a crash in between sends the events again (at least once)
+        let rejected: HashMap<usize, String> = publisher.publish(events.iter().map(PublishEvent::from).collect())
+          .await.map_err(|e| RetryableError::new(&e.to_string()))?
+          .into_iter()
+          .map(|rejected| (rejected.index, rejected.reason))
+          .collect();
+        for (index, event) in events.iter().enumerate() {
+          let event_key = EventKey::new(event.event_id.clone()).map_err(|e| PermanentError::new(&e.to_string()))?;
+          let result = match rejected.get(&index) {
+            // kept as failed so it can be inspected and fixed, it would block the shard in the outbox
+            Some(reason) => {
+              tracing::error!("outbox event {} can never be published, keeping it as failed: {}", event.event_id, reason);
+              let outbox_item = event.to_item().map_err(|e| PermanentError::new(&e.to_string()))?;
+              self.database_store_service.db_fail_outbox_event(outbox_key.clone(), event_key, outbox_item, reason.clone()).await
+            },
+            None => self.database_store_service.db_delete_outbox_event(outbox_key.clone(), event_key).await,
+          };
+          result.map_err(|e| RetryableError::new(&e.to_string()))?;
+        }
+        relayed += events.len();
+        if events.len() < OUTBOX_RELAY_BATCH_SIZE as usize {
+          break;
+        }
+      }
+    }
 
+    Ok(relayed)
+  }
+
+  async fn subscribe_topic(&self, user_key: UserKey, topic_id: String) -> Result<(), ApplicationError> {
+    let subscription_key = SubscriptionKey::new(topic_id).map_err(|e| PermanentError::new(&e.to_string()))?;
     self.database_store_service
-      .db_create_notification_message(item, condition_expression.to_string())
+      .db_put_topic_subscription(user_key, subscription_key)
       .await.map_err(|e| RetryableError::new(&e.to_string()))?;
-
     Ok(())
   }
+
+  async fn get_topic_subscriptions(&self, user_key: UserKey) -> Result<Vec<String>, ApplicationError> {
+    let items = self.database_store_service
+      .db_get_topic_subscriptions(user_key)
+      .await.map_err(|e| RetryableError::new(&e.to_string()))?;
+    let mut topic_ids = vec![];
+    for item in items {
+      let sk = item.get("SK").and_then(|sk| sk.as_s().ok()).map(String::as_str).unwrap_or_default();
+      topic_ids.push(SubscriptionKey::decode(sk).map_err(|e| PermanentError::new(&e.to_string()))?.id().to_string());
+    }
+    Ok(topic_ids)
+  }
+
+  async fn may_follow_topic(&self, user_key: UserKey, topic_id: String) -> Result<bool, ApplicationError> {
+    // taking part in a topic is what brings its notifications, so having one is the membership
+    self.database_store_service
+      .db_has_topic_notification(user_key, topic_id)
+      .await.map_err(|e| RetryableError::new(&e.to_string()).into())
+  }
 }
@@ -0,0 +1,41 @@
+use serde::Serialize;
+
+use crate::{
+    adapters::memo_events::processors::model::{DBNotifcation, DBOutboxEvent, NotificationStatus},
+    client::eventbridge_client::PublishEvent,
+};
+
+pub const EVENT_SOURCE: &str = "memo.notification";
+pub const NOTIFICATION_CREATED: &str = "memo:notification.created";
+
+pub fn status_changed_detail_type(status: &NotificationStatus) -> &'static str {
+    match status {
+        NotificationStatus::UNREAD => "memo:notification.unread",
+        NotificationStatus::READ => "memo:notification.read",
+        NotificationStatus::REMOVED => "memo:notification.removed",
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct NotificationCreatedDetail<'a> {
+    pub notification: &'a DBNotifcation,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatusChangedDetail<'a> {
+    pub user_id: &'a str,
+    pub notification_id: &'a str,
+    pub status: &'a NotificationStatus,
+    pub previous_status: &'a NotificationStatus,
+    pub updated_time: &'a str,
+}
+
+impl From<&DBOutboxEvent> for PublishEvent {
+    fn from(event: &DBOutboxEvent) -> Self {
+        PublishEvent {
+            source: event.source.clone(),
+            detail_type: event.detail_type.clone(),
+            detail: event.detail.clone(),
+        }
+    }
+}
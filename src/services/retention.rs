@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{
+    adapters::memo_events::processors::model::{NotificationStatus, NotificationType},
+    errors::main::SerializationError,
+};
+
+// How many days a notification is kept once it reaches a status, per notification type.
+// Combinations without a rule never expire, so nothing expires unless a policy is configured.
+#[derive(Debug, Clone, Default)]
+pub struct RetentionPolicy {
+    retention_days: HashMap<(NotificationType, NotificationStatus), i64>,
+}
+
+impl RetentionPolicy {
+    // Parses rules in the form `Message:READ=30,Message:REMOVED=7`
+    pub fn parse(spec: &str) -> Result<Self, SerializationError> {
+        let mut policy = RetentionPolicy::default();
+        for rule in spec.split(',').map(str::trim).filter(|rule| !rule.is_empty()) {
+            let invalid = || SerializationError::new(&format!("invalid retention rule {:?}, expected <Type>:<STATUS>=<days> with at least one day", rule));
+            let (target, days) = rule.split_once('=').ok_or_else(invalid)?;
+            let (notification_type, status) = target.split_once(':').ok_or_else(invalid)?;
+            let notification_type: NotificationType = serde_json::from_value(serde_json::Value::String(notification_type.trim().to_string()))
+                .map_err(|_| invalid())?;
+            let status: NotificationStatus = serde_json::from_value(serde_json::Value::String(status.trim().to_string()))
+                .map_err(|_| invalid())?;
+            let days = days.trim().parse::<i64>().ok().filter(|days| *days > 0).ok_or_else(invalid)?;
+            policy.retention_days.insert((notification_type, status), days);
+        }
+
+        Ok(policy)
+    }
+
+    // Epoch seconds for the DynamoDB TTL attribute, counted from the moment the status was reached
+    pub fn expires_at(&self, notification_type: &NotificationType, status: &NotificationStatus, since: DateTime<Utc>) -> Option<i64> {
+        self.retention_days
+            .get(&(notification_type.clone(), status.clone()))
+            .map(|days| (since + Duration::days(*days)).timestamp())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn only_configured_rules_expire() {
+        let since = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        assert_eq!(RetentionPolicy::default().expires_at(&NotificationType::Message, &NotificationStatus::READ, since), None);
+
+        let policy = RetentionPolicy::parse(" Message:READ=10 , Message:REMOVED=7").unwrap();
+        assert_eq!(policy.expires_at(&NotificationType::Message, &NotificationStatus::READ, since), Some((since + Duration::days(10)).timestamp()));
+        assert_eq!(policy.expires_at(&NotificationType::Message, &NotificationStatus::REMOVED, since), Some((since + Duration::days(7)).timestamp()));
+        assert_eq!(policy.expires_at(&NotificationType::Message, &NotificationStatus::UNREAD, since), None);
+    }
+
+    #[test]
+    fn parse_rejects_malformed_rules() {
+        assert!(RetentionPolicy::parse("Message:READ").is_err());
+        assert!(RetentionPolicy::parse("READ=30").is_err());
+        assert!(RetentionPolicy::parse("Message:ARCHIVED=30").is_err());
+        assert!(RetentionPolicy::parse("Alert:READ=30").is_err());
+        assert!(RetentionPolicy::parse("Message:READ=soon").is_err());
+        assert!(RetentionPolicy::parse("Message:READ=0").is_err());
+    }
+}
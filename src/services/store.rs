@@ -1,34 +1,336 @@
-use aws_sdk_dynamodb::{Client as DynamoDbClient, Error as DynamoDbError, types::AttributeValue, operation::{query::QueryOutput, get_item::GetItemOutput}};
-use std::collections::HashMap;
+use aws_sdk_dynamodb::{Client as DynamoDbClient, Error as DynamoDbError, types::{AttributeValue, Put, Update, Delete, TransactWriteItem, KeysAndAttributes, ReturnValue, ReturnValuesOnConditionCheckFailure}, types::error::ProvisionedThroughputExceededException, operation::{query::QueryOutput, get_item::GetItemOutput, scan::ScanOutput}};
+use aws_sdk_dynamodb::error::BuildError;
+use std::{collections::HashMap, time::Duration};
+use tokio::time::sleep;
+use futures::stream::{self, StreamExt};
 use async_trait::async_trait;
 
-use crate::adapters::memo_events::processors::model::NotificationStatus;
+use crate::{
+    adapters::memo_events::processors::model::{NotificationStatus, NotificationType, DBStatusChange},
+    services::keys::{EntityKey, UserKey, NotificationKey, CounterKey, SourceNotificationKey, OutboxKey, EventKey, FailedEventKey, SubscriptionKey, NotificationIdMode},
+};
 
 // Define the trait for database operations
 #[async_trait]
 pub trait DatabaseStoreInterface {
-    async fn db_create_notification_message(&self, item: HashMap<String, AttributeValue>, condition: String) -> Result<(), DynamoDbError>;
-    async fn db_update_notification_message(&self, user_id: String, noti_id: String, status: NotificationStatus) -> Result<(), DynamoDbError>;
-    async fn db_get_notifications_by_user_id(&self, user_id: String) -> Result<QueryOutput, DynamoDbError>;
+    async fn db_create_notification_message(&self, user_key: UserKey, item: HashMap<String, AttributeValue>, condition: String, source: Option<SourceMapping>, outbox_item: HashMap<String, AttributeValue>) -> Result<(), DynamoDbError>;
+    async fn db_update_notification_status(&self, user_key: UserKey, noti_key: NotificationKey, change: StatusChange) -> Result<HashMap<String, AttributeValue>, DynamoDbError>;
+    async fn db_complete_status_change(&self, user_key: UserKey, noti_key: NotificationKey, change: DBStatusChange, outbox_item: HashMap<String, AttributeValue>) -> Result<(), DynamoDbError>;
+    async fn db_get_notifications_by_user_id(&self, user_key: UserKey) -> Result<QueryOutput, DynamoDbError>;
     async fn db_get_notification_item_with_pk_sk(&self, pk_value: String, sk_value: String) -> Result<GetItemOutput, DynamoDbError>;
+    async fn db_get_unread_count(&self, user_key: UserKey) -> Result<GetItemOutput, DynamoDbError>;
+    async fn db_get_notification_states(&self, user_key: UserKey) -> Result<Vec<HashMap<String, AttributeValue>>, DynamoDbError>;
+    async fn db_set_unread_count(&self, user_key: UserKey, expected: i64, count: i64) -> Result<(), DynamoDbError>;
+    async fn db_scan_user_partitions(&self, exclusive_start_key: Option<HashMap<String, AttributeValue>>) -> Result<ScanOutput, DynamoDbError>;
+    async fn db_get_all_notifications_by_user_id(&self, user_key: UserKey, exclusive_start_key: Option<HashMap<String, AttributeValue>>) -> Result<QueryOutput, DynamoDbError>;
+    async fn db_batch_get_notifications(&self, user_key: UserKey, noti_keys: Vec<NotificationKey>) -> Result<Vec<HashMap<String, AttributeValue>>, DynamoDbError>;
+    async fn db_bulk_update_notification_messages(&self, user_key: UserKey, updates: Vec<(NotificationKey, StatusTransition)>) -> Result<(), DynamoDbError>;
+    async fn db_scan_expiring_notifications(&self, horizon: i64, exclusive_start_key: Option<HashMap<String, AttributeValue>>) -> Result<ScanOutput, DynamoDbError>;
+    async fn db_mark_notification_archived(&self, user_key: UserKey, noti_key: NotificationKey, version: i64, archived_at: i64) -> Result<(), DynamoDbError>;
+    async fn db_batch_create_notification_messages(&self, creates: Vec<BatchCreate>) -> BatchCreated;
+    async fn db_get_outbox_events(&self, outbox_key: OutboxKey, limit: i32) -> Result<QueryOutput, DynamoDbError>;
+    async fn db_delete_outbox_event(&self, outbox_key: OutboxKey, event_key: EventKey) -> Result<(), DynamoDbError>;
+    async fn db_fail_outbox_event(&self, outbox_key: OutboxKey, event_key: EventKey, outbox_item: HashMap<String, AttributeValue>, reason: String) -> Result<(), DynamoDbError>;
+    async fn db_put_topic_subscription(&self, user_key: UserKey, subscription_key: SubscriptionKey) -> Result<(), DynamoDbError>;
+    async fn db_get_topic_subscriptions(&self, user_key: UserKey) -> Result<Vec<HashMap<String, AttributeValue>>, DynamoDbError>;
+    async fn db_has_topic_notification(&self, user_key: UserKey, topic_id: String) -> Result<bool, DynamoDbError>;
 }
 
+// Everything a conditional status update needs to know about the item it expects to find
+#[derive(Debug, Clone)]
+pub struct StatusTransition {
+    pub notification_type: NotificationType,
+    pub from_status: NotificationStatus,
+    pub status: NotificationStatus,
+    pub updated_time: String,
+    pub expires_at: Option<i64>,
+    // only apply the update if the item is still at this version
+    pub expected_version: Option<i64>,
+    // domain event committed together with the update
+    pub outbox_item: Option<HashMap<String, AttributeValue>>,
+}
+
+// One notification's status change as a single UpdateItem. The item records the change as pending
+// until `db_complete_status_change` writes its outbox event and counter update
+#[derive(Debug, Clone)]
+pub struct StatusChange {
+    pub notification_type: NotificationType,
+    pub status: NotificationStatus,
+    pub updated_time: String,
+    pub expires_at: Option<i64>,
+    pub expected_version: Option<i64>,
+    pub event_key: EventKey,
+}
+
+// Idempotency record for generated notification ids, a replayed upstream event finds it and fails the transaction
+#[derive(Debug, Clone)]
+pub struct SourceMapping {
+    pub source_key: SourceNotificationKey,
+    pub notification_key: NotificationKey,
+    pub expires_at: Option<i64>,
+}
+
+// One notification of a fan-out event, created like a single notification and skipped if it exists
+#[derive(Debug, Clone)]
+pub struct BatchCreate {
+    pub user_key: UserKey,
+    pub item: HashMap<String, AttributeValue>,
+    pub source: Option<SourceMapping>,
+    pub outbox_item: HashMap<String, AttributeValue>,
+}
+
+// Outcome of a fan-out create. Recipients that already had the notification are in neither list
+#[derive(Debug, Default)]
+pub struct BatchCreated {
+    pub created: Vec<UserKey>,
+    pub failed: Vec<(UserKey, DynamoDbError)>,
+}
+
+impl BatchCreated {
+    fn record(&mut self, user_key: UserKey, result: Result<(), DynamoDbError>) {
+        match result {
+            Ok(()) => self.created.push(user_key),
+            // the recipient already has the notification
+            Err(DynamoDbError::TransactionCanceledException(e))
+                if e.cancellation_reasons().iter().any(|reason| reason.code() == Some("ConditionalCheckFailed")) => {},
+            Err(e) => self.failed.push((user_key, e)),
+        }
+    }
+}
+
+// DynamoDB caps a transaction at 100 actions, every update brings its outbox event and one slot is kept for the counter update
+pub const BULK_UPDATE_CHUNK_SIZE: usize = 49;
+const BATCH_GET_CHUNK_SIZE: usize = 100;
+// a batch whose unprocessed part keeps coming back fails after this many requests
+const BATCH_MAX_ATTEMPTS: usize = 8;
+const BATCH_INITIAL_BACKOFF_MILLIS: u64 = 50;
+const BATCH_MAX_BACKOFF_MILLIS: u64 = 2000;
+// fan-out transactions in flight at once
+const BATCH_CREATE_CONCURRENCY: usize = 16;
+
 // Define the struct implementing the trait
 #[derive(Debug, Clone)]
 pub struct DatabaseStoreService {
     pub store: DynamoDbClient,
     pub table_name: String,
+    pub notification_id_mode: NotificationIdMode,
+    // name of the GSI that orders a user's notifications by `created_time`
+    pub created_time_index: String,
+}
+
+impl DatabaseStoreService {
+    // Builds the counter update that goes into the same transaction as the notification write
+    fn unread_counter_update(&self, user_key: &UserKey, delta: i64) -> Result<TransactWriteItem, BuildError> {
+        let update = Update::builder()
+            .table_name(self.table_name.clone())
+            .key("PK", user_key.attribute())
+            .key("SK", CounterKey::unread().attribute())
+            .update_expression("ADD #unread_count :delta")
+            .expression_attribute_names("#unread_count", "unread_count")
+            .expression_attribute_values(":delta", AttributeValue::N(delta.to_string()))
+            .build()?;
+
+        Ok(TransactWriteItem::builder().update(update).build())
+    }
+
+    // The condition on the previous status keeps the counter in step with concurrent updates,
+    // the allowed predecessors enforce the status state machine on the item itself.
+    // On a failed condition the current item comes back in the cancellation reason, or none if it does not exist
+    fn status_update(&self, user_key: &UserKey, noti_key: &NotificationKey, transition: &StatusTransition) -> Result<TransactWriteItem, BuildError> {
+        let mut expression = StatusExpression::new(&transition.notification_type, &transition.status, &transition.updated_time, transition.expires_at, transition.expected_version);
+        expression.condition.push_str(" AND #status = :from_status");
+        expression.values.insert(":from_status".to_string(), AttributeValue::S(format!("{:?}", transition.from_status)));
+
+        let update = Update::builder()
+            .table_name(self.table_name.clone())
+            .key("PK".to_string(), user_key.attribute())
+            .key("SK".to_string(), noti_key.attribute())
+            .update_expression(expression.update_expression())
+            .condition_expression(expression.condition)
+            .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld)
+            .set_expression_attribute_names(Some(expression.names))
+            .set_expression_attribute_values(Some(expression.values))
+            .build()?;
+
+        Ok(TransactWriteItem::builder().update(update).build())
+    }
+
+    fn outbox_put(&self, outbox_item: HashMap<String, AttributeValue>) -> Result<TransactWriteItem, BuildError> {
+        let put = Put::builder()
+            .table_name(self.table_name.clone())
+            .set_item(Some(outbox_item))
+            .build()?;
+
+        Ok(TransactWriteItem::builder().put(put).build())
+    }
+
+    async fn batch_get_items(&self, keys: Vec<HashMap<String, AttributeValue>>, projection: Option<&str>) -> Result<Vec<HashMap<String, AttributeValue>>, DynamoDbError> {
+        let mut items = vec![];
+        for chunk in keys.chunks(BATCH_GET_CHUNK_SIZE) {
+            // read before a conditional write, a stale copy would fail the condition for nothing
+            let keys_and_attributes = KeysAndAttributes::builder()
+                .set_keys(Some(chunk.to_vec()))
+                .set_projection_expression(projection.map(str::to_string))
+                .consistent_read(true)
+                .build()?;
+            let mut request_items = Some(HashMap::from([(self.table_name.clone(), keys_and_attributes)]));
+
+            // unprocessed keys mean the partition is throttled, back off before asking for them again
+            let mut backoff_millis = BATCH_INITIAL_BACKOFF_MILLIS;
+            for attempt in 1.. {
+                let pending = match request_items.take().filter(|pending| !pending.is_empty()) {
+                    Some(pending) => pending,
+                    None => break,
+                };
+                if attempt > BATCH_MAX_ATTEMPTS {
+                    return Err(throttled("BatchGetItem"));
+                }
+                if attempt > 1 {
+                    sleep(Duration::from_millis(backoff_millis)).await;
+                    backoff_millis = (backoff_millis * 2).min(BATCH_MAX_BACKOFF_MILLIS);
+                }
+                let result = self.store.batch_get_item()
+                    .set_request_items(Some(pending))
+                    .send()
+                    .await?;
+                if let Some(mut responses) = result.responses {
+                    items.extend(responses.remove(&self.table_name).unwrap_or_default());
+                }
+                request_items = result.unprocessed_keys;
+            }
+        }
+
+        Ok(items)
+    }
+}
+
+// Reported when a batch still has unprocessed entries after BATCH_MAX_ATTEMPTS requests
+fn throttled(operation: &str) -> DynamoDbError {
+    DynamoDbError::ProvisionedThroughputExceededException(
+        ProvisionedThroughputExceededException::builder()
+            .message(format!("{} still has unprocessed entries after {} attempts", operation, BATCH_MAX_ATTEMPTS))
+            .build()
+    )
+}
+
+// UNREAD -> READ/REMOVED decrements the counter, READ/REMOVED -> UNREAD increments it
+fn unread_count_delta(from_status: &NotificationStatus, status: &NotificationStatus) -> i64 {
+    match (from_status, status) {
+        (NotificationStatus::UNREAD, NotificationStatus::UNREAD) => 0,
+        (NotificationStatus::UNREAD, _) => -1,
+        (_, NotificationStatus::UNREAD) => 1,
+        _ => 0,
+    }
+}
+
+// Clauses and placeholders shared by the status updates. The notification type is checked since the
+// retention period depends on it, an expected version pins the update to the item the caller last saw
+struct StatusExpression {
+    set: Vec<String>,
+    remove: Vec<String>,
+    condition: String,
+    names: HashMap<String, String>,
+    values: HashMap<String, AttributeValue>,
+}
+
+impl StatusExpression {
+    fn new(notification_type: &NotificationType, status: &NotificationStatus, updated_time: &str, expires_at: Option<i64>, expected_version: Option<i64>) -> Self {
+        let mut set = vec![
+            "#status = :new_status".to_string(),
+            "#updated_time = :updated_time".to_string(),
+            "#version = if_not_exists(#version, :zero) + :one".to_string(),
+        ];
+        let mut remove = vec![];
+        let mut names = HashMap::from([
+            ("#status".to_string(), "status".to_string()),
+            ("#updated_time".to_string(), "updated_time".to_string()),
+            ("#notification_type".to_string(), "notification_type".to_string()),
+            ("#expires_at".to_string(), "expires_at".to_string()),
+            ("#version".to_string(), "version".to_string()),
+        ]);
+        let mut values = HashMap::from([
+            (":zero".to_string(), AttributeValue::N("0".to_string())),
+            (":one".to_string(), AttributeValue::N("1".to_string())),
+            (":new_status".to_string(), AttributeValue::S(format!("{:?}", status))),
+            (":notification_type".to_string(), AttributeValue::S(format!("{:?}", notification_type))),
+            (":updated_time".to_string(), AttributeValue::S(updated_time.to_string())),
+        ]);
+        match status {
+            NotificationStatus::READ => {
+                set.push("#read_at = :updated_time".to_string());
+                names.insert("#read_at".to_string(), "read_at".to_string());
+            },
+            NotificationStatus::REMOVED => {
+                set.push("#removed_at = :updated_time".to_string());
+                names.insert("#removed_at".to_string(), "removed_at".to_string());
+            },
+            NotificationStatus::UNREAD => {},
+        }
+        match expires_at {
+            Some(expires_at) => {
+                set.push("#expires_at = :expires_at".to_string());
+                values.insert(":expires_at".to_string(), AttributeValue::N(expires_at.to_string()));
+            },
+            None => remove.push("#expires_at".to_string()),
+        }
+        // the new status is archived again before it expires
+        remove.push("#archived_at".to_string());
+        names.insert("#archived_at".to_string(), "archived_at".to_string());
+
+        let mut allowed_values = vec![];
+        for (i, allowed_status) in status.allowed_predecessors().iter().enumerate() {
+            let value = format!(":allowed_{}", i);
+            values.insert(value.clone(), AttributeValue::S(format!("{:?}", allowed_status)));
+            allowed_values.push(value);
+        }
+        let mut condition = format!(
+            "attribute_exists(SK) AND #notification_type = :notification_type AND #status IN ({})",
+            allowed_values.join(", "),
+        );
+        if let Some(expected_version) = expected_version {
+            // items written before versioning have no attribute and count as version 0
+            condition.push_str(match expected_version {
+                0 => " AND (attribute_not_exists(#version) OR #version = :expected_version)",
+                _ => " AND #version = :expected_version",
+            });
+            values.insert(":expected_version".to_string(), AttributeValue::N(expected_version.to_string()));
+        }
+
+        StatusExpression { set, remove, condition, names, values }
+    }
+
+    fn update_expression(&self) -> String {
+        let mut expression = format!("SET {}", self.set.join(", "));
+        if !self.remove.is_empty() {
+            expression.push_str(&format!(" REMOVE {}", self.remove.join(", ")));
+        }
+        expression
+    }
 }
 
 #[async_trait]
 impl DatabaseStoreInterface for DatabaseStoreService {
-    async fn db_create_notification_message(&self, item: HashMap<String, AttributeValue>, condition: String) -> Result<(), DynamoDbError> {
-        let _ = self.store.put_item()
+    async fn db_create_notification_message(&self, user_key: UserKey, item: HashMap<String, AttributeValue>, condition: String, source: Option<SourceMapping>, outbox_item: HashMap<String, AttributeValue>) -> Result<(), DynamoDbError> {
+        let put = Put::builder()
             .table_name(self.table_name.clone()) //memo-management
             .set_item(Some(item))
             .condition_expression(condition)
-            .send()
-            .await?;
+            .build()?;
+
+        // new notifications are always UNREAD, so the counter moves with the put
+        let mut transaction = self.store.transact_write_items()
+            .transact_items(TransactWriteItem::builder().put(put).build())
+            .transact_items(self.unread_counter_update(&user_key, 1)?)
+            .transact_items(self.outbox_put(outbox_item)?);
+        if let Some(source) = source {
+            let mapping = Put::builder()
+                .table_name(self.table_name.clone())
+                .set_item(Some(source_mapping_item(&user_key, &source)))
+                .condition_expression("attribute_not_exists(SK)")
+                .build()?;
+            transaction = transaction.transact_items(TransactWriteItem::builder().put(mapping).build());
+        }
+        transaction.send().await?;
 
         Ok(())
     }
@@ -38,57 +340,450 @@ impl DatabaseStoreInterface for DatabaseStoreService {
         key.insert("PK".to_string(), AttributeValue::S(pk_value.to_string()));
         key.insert("SK".to_string(), AttributeValue::S(sk_value.to_string()));
 
+        // strongly consistent so a read right after a write sees that write
         let result = self.store.get_item()
             .table_name(self.table_name.clone())
             .set_key(Some(key))
+            .consistent_read(true)
             .send()
             .await?;
 
         Ok(result)
     }
 
-    async fn db_update_notification_message(&self, user_id: String, noti_id: String, status: NotificationStatus) -> Result<(), DynamoDbError> {
-        let user_id_attr = AttributeValue::S(format!("USR#{}", user_id));
-        let noti_id_attr = AttributeValue::S(format!("NTF#{}", noti_id));
-        let status = AttributeValue::S(format!("{:?}", status));
+    async fn db_update_notification_status(&self, user_key: UserKey, noti_key: NotificationKey, change: StatusChange) -> Result<HashMap<String, AttributeValue>, DynamoDbError> {
+        let mut expression = StatusExpression::new(&change.notification_type, &change.status, &change.updated_time, change.expires_at, change.expected_version);
+        // the previous status is copied before `#status` is overwritten, SET reads the item as it was
+        expression.set.extend([
+            "#pending_event_id = :event_id".to_string(),
+            "#pending_from_status = #status".to_string(),
+            "#pending_status = :new_status".to_string(),
+            "#pending_updated_time = :updated_time".to_string(),
+        ]);
+        // a change still waiting for its event is finished before the next one starts
+        expression.condition.push_str(" AND attribute_not_exists(#pending_event_id)");
+        for name in DBStatusChange::ATTRIBUTES.iter() {
+            expression.names.insert(format!("#{}", name), name.to_string());
+        }
+        expression.values.insert(":event_id".to_string(), AttributeValue::S(change.event_key.id().to_string()));
 
-        self.store.update_item()
+        // the updated item comes back, on a failed condition the current one comes back with the error
+        let result = self.store.update_item()
             .table_name(self.table_name.clone())
-            .key("PK".to_string(), user_id_attr)
-            .key("SK".to_string(), noti_id_attr)
-            .update_expression("SET #status = :new_status")
-            .expression_attribute_names("#status", "status")
-            .expression_attribute_values(":new_status", status)
+            .key("PK", user_key.attribute())
+            .key("SK", noti_key.attribute())
+            .update_expression(expression.update_expression())
+            .condition_expression(expression.condition)
+            .set_expression_attribute_names(Some(expression.names))
+            .set_expression_attribute_values(Some(expression.values))
+            .return_values(ReturnValue::AllNew)
+            .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld)
             .send()
             .await?;
 
+        Ok(result.attributes.unwrap_or_default())
+    }
+
+    async fn db_complete_status_change(&self, user_key: UserKey, noti_key: NotificationKey, change: DBStatusChange, outbox_item: HashMap<String, AttributeValue>) -> Result<(), DynamoDbError> {
+        // clearing the marker is conditional, so only one writer gets to emit the event and move the counter
+        let mut clear = Update::builder()
+            .table_name(self.table_name.clone())
+            .key("PK", user_key.attribute())
+            .key("SK", noti_key.attribute())
+            .condition_expression("#pending_event_id = :event_id")
+            .expression_attribute_values(":event_id", AttributeValue::S(change.event_id.clone()));
+        let mut remove = vec![];
+        for name in DBStatusChange::ATTRIBUTES.iter() {
+            clear = clear.expression_attribute_names(format!("#{}", name), name.to_string());
+            remove.push(format!("#{}", name));
+        }
+        let clear = clear.update_expression(format!("REMOVE {}", remove.join(", "))).build()?;
+
+        let delta = unread_count_delta(&change.from_status, &change.status);
+        let mut transaction = self.store.transact_write_items()
+            .transact_items(TransactWriteItem::builder().update(clear).build())
+            .transact_items(self.outbox_put(outbox_item)?);
+        if delta != 0 {
+            transaction = transaction.transact_items(self.unread_counter_update(&user_key, delta)?);
+        }
+        transaction.send().await?;
+
         Ok(())
     }
 
-    async fn db_get_notifications_by_user_id(&self, user_id: String) -> Result<QueryOutput, DynamoDbError> {
+    async fn db_get_notifications_by_user_id(&self, user_key: UserKey) -> Result<QueryOutput, DynamoDbError> {
         // Implementation for getting a notification message from DynamoDB
-        let user_id_attr = AttributeValue::S(format!("USR#{}", user_id));
+        let user_id_attr = user_key.attribute();
         let unread_status_attr = AttributeValue::S(format!("{:?}", NotificationStatus::UNREAD));
 
-        // another way sort by date without using GSI is
-        // Table Structure
-        // Partition Key (PK): USER#<UserId> (e.g., USER#123)
-        // Sort Key (SK): NOTIFICATION#<Timestamp> (e.g., NOTIFICATION#2023-03-15T12:34:56Z)
-
-        let result = self.store.query()
+        let query = self.store.query()
             .table_name(self.table_name.clone())
-            .index_name("PK-created_time-index")
-            .key_condition_expression("#pk = :user_id")
             .filter_expression("#status = :unread_status")
             .expression_attribute_names("#pk", "PK")
             .expression_attribute_names("#status", "status")
             .expression_attribute_values(":user_id", user_id_attr)
             .expression_attribute_values(":unread_status", unread_status_attr)
             .scan_index_forward(false) // most recent data first
-            .limit(20)
+            .limit(20);
+
+        let query = match self.notification_id_mode {
+            NotificationIdMode::Upstream => query
+                .index_name(self.created_time_index.clone())
+                .key_condition_expression("#pk = :user_id"),
+            // ULIDs sort by creation time, so the base table is already in order and can be read consistently
+            NotificationIdMode::Ulid => query
+                .key_condition_expression("#pk = :user_id AND begins_with(#sk, :noti_prefix)")
+                .expression_attribute_names("#sk", "SK")
+                .expression_attribute_values(":noti_prefix", AttributeValue::S(NotificationKey::key_prefix()))
+                .consistent_read(true),
+        };
+        let result = query.send().await?;
+
+        Ok(result)
+    }
+
+    async fn db_get_unread_count(&self, user_key: UserKey) -> Result<GetItemOutput, DynamoDbError> {
+        self.db_get_notification_item_with_pk_sk(user_key.encode(), CounterKey::unread().encode()).await
+    }
+
+    async fn db_get_notification_states(&self, user_key: UserKey) -> Result<Vec<HashMap<String, AttributeValue>>, DynamoDbError> {
+        let mut items = vec![];
+        let mut exclusive_start_key = None;
+        loop {
+            let mut query = self.store.query()
+                .table_name(self.table_name.clone())
+                .key_condition_expression("#pk = :user_id AND begins_with(#sk, :noti_prefix)")
+                .expression_attribute_names("#pk", "PK")
+                .expression_attribute_names("#sk", "SK")
+                .expression_attribute_names("#status", "status")
+                .expression_attribute_values(":user_id", user_key.attribute())
+                .expression_attribute_values(":noti_prefix", AttributeValue::S(NotificationKey::key_prefix()))
+                .consistent_read(true)
+                .set_exclusive_start_key(exclusive_start_key);
+            let mut projection = vec!["#pk".to_string(), "#sk".to_string(), "#status".to_string()];
+            for name in DBStatusChange::ATTRIBUTES.iter() {
+                query = query.expression_attribute_names(format!("#{}", name), name.to_string());
+                projection.push(format!("#{}", name));
+            }
+            let result = query.projection_expression(projection.join(", ")).send().await?;
+
+            items.extend(result.items.unwrap_or_default());
+            exclusive_start_key = result.last_evaluated_key;
+            if exclusive_start_key.is_none() {
+                break;
+            }
+        }
+
+        Ok(items)
+    }
+
+    async fn db_set_unread_count(&self, user_key: UserKey, expected: i64, count: i64) -> Result<(), DynamoDbError> {
+        // only overwrite the value the caller counted against, a missing counter counts as 0
+        let condition = match expected {
+            0 => "attribute_not_exists(#unread_count) OR #unread_count = :expected",
+            _ => "#unread_count = :expected",
+        };
+        self.store.update_item()
+            .table_name(self.table_name.clone())
+            .key("PK", user_key.attribute())
+            .key("SK", CounterKey::unread().attribute())
+            .update_expression("SET #unread_count = :count")
+            .condition_expression(condition)
+            .expression_attribute_names("#unread_count", "unread_count")
+            .expression_attribute_values(":count", AttributeValue::N(count.to_string()))
+            .expression_attribute_values(":expected", AttributeValue::N(expected.to_string()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    async fn db_scan_user_partitions(&self, exclusive_start_key: Option<HashMap<String, AttributeValue>>) -> Result<ScanOutput, DynamoDbError> {
+        // only the partition keys are needed to know which users to reconcile
+        let result = self.store.scan()
+            .table_name(self.table_name.clone())
+            .projection_expression("#pk")
+            .filter_expression("begins_with(#pk, :user_prefix)")
+            .expression_attribute_names("#pk", "PK")
+            .expression_attribute_values(":user_prefix", AttributeValue::S(UserKey::key_prefix()))
+            .set_exclusive_start_key(exclusive_start_key)
             .send()
             .await?;
 
         Ok(result)
     }
+
+    async fn db_get_all_notifications_by_user_id(&self, user_key: UserKey, exclusive_start_key: Option<HashMap<String, AttributeValue>>) -> Result<QueryOutput, DynamoDbError> {
+        let result = self.store.query()
+            .table_name(self.table_name.clone())
+            .key_condition_expression("#pk = :user_id AND begins_with(#sk, :noti_prefix)")
+            .expression_attribute_names("#pk", "PK")
+            .expression_attribute_names("#sk", "SK")
+            .expression_attribute_values(":user_id", user_key.attribute())
+            .expression_attribute_values(":noti_prefix", AttributeValue::S(NotificationKey::key_prefix()))
+            .consistent_read(true)
+            .set_exclusive_start_key(exclusive_start_key)
+            .send()
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn db_batch_get_notifications(&self, user_key: UserKey, noti_keys: Vec<NotificationKey>) -> Result<Vec<HashMap<String, AttributeValue>>, DynamoDbError> {
+        let keys = noti_keys.iter().map(|noti_key| {
+            HashMap::from([
+                ("PK".to_string(), user_key.attribute()),
+                ("SK".to_string(), noti_key.attribute()),
+            ])
+        }).collect::<Vec<_>>();
+
+        self.batch_get_items(keys, None).await
+    }
+
+    async fn db_bulk_update_notification_messages(&self, user_key: UserKey, updates: Vec<(NotificationKey, StatusTransition)>) -> Result<(), DynamoDbError> {
+        let mut delta = 0;
+        let mut transaction = self.store.transact_write_items();
+        for (noti_key, transition) in updates.iter() {
+            delta += unread_count_delta(&transition.from_status, &transition.status);
+            transaction = transaction.transact_items(self.status_update(&user_key, noti_key, transition)?);
+        }
+        // outbox events follow the updates so cancellation reasons still line up with `updates`
+        for (_, transition) in updates.iter() {
+            if let Some(outbox_item) = &transition.outbox_item {
+                transaction = transaction.transact_items(self.outbox_put(outbox_item.clone())?);
+            }
+        }
+        // the counter update goes last
+        if delta != 0 {
+            transaction = transaction.transact_items(self.unread_counter_update(&user_key, delta)?);
+        }
+        transaction.send().await?;
+
+        Ok(())
+    }
+
+    async fn db_scan_expiring_notifications(&self, horizon: i64, exclusive_start_key: Option<HashMap<String, AttributeValue>>) -> Result<ScanOutput, DynamoDbError> {
+        let result = self.store.scan()
+            .table_name(self.table_name.clone())
+            .filter_expression("begins_with(#sk, :noti_prefix) AND #expires_at <= :horizon AND attribute_not_exists(#archived_at)")
+            .expression_attribute_names("#sk", "SK")
+            .expression_attribute_names("#expires_at", "expires_at")
+            .expression_attribute_names("#archived_at", "archived_at")
+            .expression_attribute_values(":noti_prefix", AttributeValue::S(NotificationKey::key_prefix()))
+            .expression_attribute_values(":horizon", AttributeValue::N(horizon.to_string()))
+            .set_exclusive_start_key(exclusive_start_key)
+            .send()
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn db_mark_notification_archived(&self, user_key: UserKey, noti_key: NotificationKey, version: i64, archived_at: i64) -> Result<(), DynamoDbError> {
+        // the archived copy must be the current one, and an item the TTL already removed stays removed
+        let condition = match version {
+            0 => "attribute_exists(SK) AND (attribute_not_exists(#version) OR #version = :version)",
+            _ => "attribute_exists(SK) AND #version = :version",
+        };
+        self.store.update_item()
+            .table_name(self.table_name.clone())
+            .key("PK", user_key.attribute())
+            .key("SK", noti_key.attribute())
+            // a changed item gets a new version, so an ETag taken before it no longer matches
+            .update_expression("SET #archived_at = :archived_at, #version = if_not_exists(#version, :zero) + :one")
+            .condition_expression(condition)
+            .expression_attribute_names("#archived_at", "archived_at")
+            .expression_attribute_names("#version", "version")
+            .expression_attribute_values(":archived_at", AttributeValue::N(archived_at.to_string()))
+            .expression_attribute_values(":version", AttributeValue::N(version.to_string()))
+            .expression_attribute_values(":zero", AttributeValue::N("0".to_string()))
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    async fn db_batch_create_notification_messages(&self, creates: Vec<BatchCreate>) -> BatchCreated {
+        // one conditional transaction per recipient, so a replay or a concurrent delivery of the same
+        // event creates each notification, its counter update and its event at most once
+        let results = stream::iter(creates)
+            .map(|create| async move {
+                let result = self.db_create_notification_message(
+                    create.user_key.clone(),
+                    create.item,
+                    "attribute_not_exists(PK) AND attribute_not_exists(SK)".to_string(),
+                    create.source,
+                    create.outbox_item,
+                ).await;
+                (create.user_key, result)
+            })
+            .buffer_unordered(BATCH_CREATE_CONCURRENCY);
+
+        results
+            .fold(BatchCreated::default(), |mut batch, (user_key, result)| async move {
+                batch.record(user_key, result);
+                batch
+            })
+            .await
+    }
+
+    async fn db_get_outbox_events(&self, outbox_key: OutboxKey, limit: i32) -> Result<QueryOutput, DynamoDbError> {
+        // oldest first, event ids are ULIDs
+        let result = self.store.query()
+            .table_name(self.table_name.clone())
+            .key_condition_expression("#pk = :pk AND begins_with(#sk, :event_prefix)")
+            .expression_attribute_names("#pk", "PK")
+            .expression_attribute_names("#sk", "SK")
+            .expression_attribute_values(":pk", outbox_key.attribute())
+            .expression_attribute_values(":event_prefix", AttributeValue::S(EventKey::key_prefix()))
+            .scan_index_forward(true)
+            .consistent_read(true)
+            .limit(limit)
+            .send()
+            .await?;
+
+        Ok(result)
+    }
+
+    async fn db_delete_outbox_event(&self, outbox_key: OutboxKey, event_key: EventKey) -> Result<(), DynamoDbError> {
+        self.store.delete_item()
+            .table_name(self.table_name.clone())
+            .key("PK", outbox_key.attribute())
+            .key("SK", event_key.attribute())
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    async fn db_fail_outbox_event(&self, outbox_key: OutboxKey, event_key: EventKey, mut outbox_item: HashMap<String, AttributeValue>, reason: String) -> Result<(), DynamoDbError> {
+        // moved out of the relayed range in one transaction, so it is neither lost nor relayed again
+        outbox_item.insert("SK".to_string(), FailedEventKey::for_event(&event_key).attribute());
+        outbox_item.insert("failed_reason".to_string(), AttributeValue::S(reason));
+        outbox_item.insert("failed_time".to_string(), AttributeValue::S(chrono::Utc::now().to_rfc3339()));
+        let put = Put::builder()
+            .table_name(self.table_name.clone())
+            .set_item(Some(outbox_item))
+            .build()?;
+        let delete = Delete::builder()
+            .table_name(self.table_name.clone())
+            .key("PK", outbox_key.attribute())
+            .key("SK", event_key.attribute())
+            .build()?;
+        self.store.transact_write_items()
+            .transact_items(TransactWriteItem::builder().put(put).build())
+            .transact_items(TransactWriteItem::builder().delete(delete).build())
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    async fn db_put_topic_subscription(&self, user_key: UserKey, subscription_key: SubscriptionKey) -> Result<(), DynamoDbError> {
+        // no `created_time`, so subscriptions stay out of the created time index and notification lists
+        self.store.put_item()
+            .table_name(self.table_name.clone())
+            .item("PK", user_key.attribute())
+            .item("SK", subscription_key.attribute())
+            .item("topic_id", AttributeValue::S(subscription_key.id().to_string()))
+            .item("subscribed_time", AttributeValue::S(chrono::Utc::now().to_rfc3339()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    async fn db_get_topic_subscriptions(&self, user_key: UserKey) -> Result<Vec<HashMap<String, AttributeValue>>, DynamoDbError> {
+        let mut items = vec![];
+        let mut exclusive_start_key = None;
+        loop {
+            let result = self.store.query()
+                .table_name(self.table_name.clone())
+                .key_condition_expression("#pk = :user_id AND begins_with(#sk, :subscription_prefix)")
+                .expression_attribute_names("#pk", "PK")
+                .expression_attribute_names("#sk", "SK")
+                .expression_attribute_values(":user_id", user_key.attribute())
+                .expression_attribute_values(":subscription_prefix", AttributeValue::S(SubscriptionKey::key_prefix()))
+                .set_exclusive_start_key(exclusive_start_key)
+                .send()
+                .await?;
+
+            items.extend(result.items.unwrap_or_default());
+            exclusive_start_key = result.last_evaluated_key;
+            if exclusive_start_key.is_none() {
+                break;
+            }
+        }
+
+        Ok(items)
+    }
+
+    async fn db_has_topic_notification(&self, user_key: UserKey, topic_id: String) -> Result<bool, DynamoDbError> {
+        // the filter applies after each page is read, so an empty page does not mean there is none
+        let mut exclusive_start_key = None;
+        loop {
+            let result = self.store.query()
+                .table_name(self.table_name.clone())
+                .key_condition_expression("#pk = :user_id AND begins_with(#sk, :noti_prefix)")
+                .filter_expression("#topic_id = :topic_id")
+                .projection_expression("#sk")
+                .expression_attribute_names("#pk", "PK")
+                .expression_attribute_names("#sk", "SK")
+                .expression_attribute_names("#topic_id", "topic_id")
+                .expression_attribute_values(":user_id", user_key.attribute())
+                .expression_attribute_values(":noti_prefix", AttributeValue::S(NotificationKey::key_prefix()))
+                .expression_attribute_values(":topic_id", AttributeValue::S(topic_id.clone()))
+                .set_exclusive_start_key(exclusive_start_key)
+                .send()
+                .await?;
+
+            if result.count > 0 {
+                return Ok(true);
+            }
+            exclusive_start_key = result.last_evaluated_key;
+            if exclusive_start_key.is_none() {
+                return Ok(false);
+            }
+        }
+    }
+}
+
+// Idempotency record that points an upstream notification id at the generated one
+fn source_mapping_item(user_key: &UserKey, source: &SourceMapping) -> HashMap<String, AttributeValue> {
+    let mut item = HashMap::from([
+        ("PK".to_string(), user_key.attribute()),
+        ("SK".to_string(), source.source_key.attribute()),
+        ("notification_id".to_string(), AttributeValue::S(source.notification_key.id().to_string())),
+    ]);
+    if let Some(expires_at) = source.expires_at {
+        item.insert("expires_at".to_string(), AttributeValue::N(expires_at.to_string()));
+    }
+    item
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_dynamodb::types::{CancellationReason, error::{TransactionCanceledException, InternalServerError}};
+
+    fn canceled(codes: &[&str]) -> DynamoDbError {
+        let reasons = codes.iter().map(|code| CancellationReason::builder().code(*code).build()).collect();
+        DynamoDbError::TransactionCanceledException(TransactionCanceledException::builder().set_cancellation_reasons(Some(reasons)).build())
+    }
+
+    #[test]
+    fn batch_created_skips_existing_recipients() {
+        let created = UserKey::new("created".to_string()).unwrap();
+        let existing = UserKey::new("existing".to_string()).unwrap();
+        let failing = UserKey::new("failing".to_string()).unwrap();
+
+        let mut batch = BatchCreated::default();
+        batch.record(created.clone(), Ok(()));
+        batch.record(existing.clone(), Err(canceled(&["ConditionalCheckFailed", "None", "None"])));
+        batch.record(failing.clone(), Err(DynamoDbError::InternalServerError(InternalServerError::builder().build())));
+
+        assert_eq!(batch.created, vec![created]);
+        let failed: Vec<_> = batch.failed.iter().map(|(user_key, _)| user_key.clone()).collect();
+        assert_eq!(failed, vec![failing]);
+    }
 }
@@ -0,0 +1,237 @@
+use std::{collections::BTreeSet, fmt, future::Future, time::Duration};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+    task::JoinSet,
+    time::timeout,
+};
+
+use crate::errors::main::SerializationError;
+
+// The long running parts of the service, READER and SERVER are preset selections of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Component {
+    Poller,
+    Reconciler,
+    Archiver,
+    Server,
+    OutboxRelay,
+}
+
+impl Component {
+    pub fn parse(value: &str) -> Result<Self, SerializationError> {
+        match value.trim() {
+            "POLLER" => Ok(Component::Poller),
+            "RECONCILER" => Ok(Component::Reconciler),
+            "ARCHIVER" => Ok(Component::Archiver),
+            "SERVER" => Ok(Component::Server),
+            "OUTBOX_RELAY" => Ok(Component::OutboxRelay),
+            _ => Err(SerializationError::new(&format!(
+                "invalid component {:?}, expected POLLER, RECONCILER, ARCHIVER, SERVER or OUTBOX_RELAY", value
+            ))),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Component::Poller => "POLLER",
+            Component::Reconciler => "RECONCILER",
+            Component::Archiver => "ARCHIVER",
+            Component::Server => "SERVER",
+            Component::OutboxRelay => "OUTBOX_RELAY",
+        }
+    }
+}
+
+// Every MEMO_MODULE and what it runs unless MEMO_COMPONENTS says otherwise, MIGRATE and the
+// command line tools run to completion instead
+const MODULES: &[(&str, &[Component])] = &[
+    ("READER", &[Component::Poller, Component::Reconciler, Component::Archiver]),
+    ("SERVER", &[Component::Server, Component::OutboxRelay]),
+    ("ALL", &[Component::Poller, Component::Reconciler, Component::Archiver, Component::Server, Component::OutboxRelay]),
+    ("MIGRATE", &[]),
+    ("REDRIVE", &[]),
+    ("SEND_TEST_EVENT", &[]),
+    ("INSPECT_USER", &[]),
+];
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Components(BTreeSet<Component>);
+
+impl Components {
+    pub fn for_module(module: &str) -> Option<Self> {
+        MODULES.iter()
+            .find(|(name, _)| *name == module)
+            .map(|(_, components)| Components(components.iter().copied().collect()))
+    }
+
+    // The valid MEMO_MODULE values, for error messages
+    pub fn module_names() -> Vec<&'static str> {
+        MODULES.iter().map(|(name, _)| *name).collect()
+    }
+
+    // Comma separated component names, e.g. `POLLER,SERVER`
+    pub fn parse(value: &str) -> Result<Self, SerializationError> {
+        let components = value.split(',')
+            .filter(|name| !name.trim().is_empty())
+            .map(Component::parse)
+            .collect::<Result<BTreeSet<Component>, _>>()?;
+        if components.is_empty() {
+            return Err(SerializationError::new("at least one component must be enabled"));
+        }
+        Ok(Components(components))
+    }
+
+    pub fn contains(&self, component: Component) -> bool {
+        self.0.contains(&component)
+    }
+}
+
+impl fmt::Display for Components {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<&str> = self.0.iter().map(Component::name).collect();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+pub struct SupervisorOption {
+    // how long graceful components get to finish once shutdown starts
+    pub shutdown_timeout_seconds: Option<u64>,
+}
+
+// Runs the enabled components side by side. A stop signal or any component ending on its own
+// shuts all of them down, so a process never keeps running half of its components
+pub struct Supervisor {
+    tasks: JoinSet<&'static str>,
+    shutdown: watch::Sender<bool>,
+    shutdown_timeout: Duration,
+}
+
+impl Supervisor {
+    pub fn new(option: SupervisorOption) -> Self {
+        let (shutdown, _) = watch::channel(false);
+        Supervisor {
+            tasks: JoinSet::new(),
+            shutdown,
+            shutdown_timeout: Duration::from_secs(option.shutdown_timeout_seconds.unwrap_or(30)),
+        }
+    }
+
+    // Resolves once shutdown starts, for components that wind down on their own
+    pub fn shutdown_signal(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut receiver = self.shutdown.subscribe();
+        async move {
+            let _ = receiver.wait_for(|stopping| *stopping).await;
+        }
+    }
+
+    // Background jobs are simply dropped on shutdown, each of their passes is safe to repeat
+    pub fn spawn<F>(&mut self, name: &'static str, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let shutdown = self.shutdown_signal();
+        self.tasks.spawn(async move {
+            tokio::select! {
+                _ = task => {},
+                _ = shutdown => {},
+            }
+            name
+        });
+    }
+
+    // The task watches `shutdown_signal` itself and is waited for up to the shutdown timeout
+    pub fn spawn_graceful<F>(&mut self, name: &'static str, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.spawn(async move {
+            task.await;
+            name
+        });
+    }
+
+    // Returns false when a component stopped on its own
+    pub async fn run(mut self) -> bool {
+        if self.tasks.is_empty() {
+            tracing::error!("No components to run");
+            return false;
+        }
+        let healthy = tokio::select! {
+            _ = stop_requested() => {
+                tracing::info!("Stop requested, shutting down");
+                true
+            },
+            Some(result) = self.tasks.join_next() => {
+                match result {
+                    Ok(name) => tracing::error!("{} stopped unexpectedly, shutting down", name),
+                    Err(e) => tracing::error!("component failed, shutting down: {:?}", e),
+                }
+                false
+            },
+        };
+
+        let _ = self.shutdown.send(true);
+        let tasks = &mut self.tasks;
+        let drained = timeout(self.shutdown_timeout, async move {
+            while let Some(result) = tasks.join_next().await {
+                match result {
+                    Ok(name) => tracing::info!("{} stopped", name),
+                    Err(e) => tracing::error!("component failed while stopping: {:?}", e),
+                }
+            }
+        }).await;
+        if drained.is_err() {
+            tracing::error!("components did not stop within {:?}, aborting them", self.shutdown_timeout);
+            self.tasks.abort_all();
+        }
+        healthy
+    }
+}
+
+async fn stop_requested() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            tracing::error!("Failed to listen for SIGTERM: {:?}", e);
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = terminate.recv() => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_component_lists() {
+        let components = Components::parse("SERVER, POLLER,,OUTBOX_RELAY").unwrap();
+        assert!(components.contains(Component::Server));
+        assert!(components.contains(Component::Poller));
+        assert!(components.contains(Component::OutboxRelay));
+        assert!(!components.contains(Component::Archiver));
+        // displayed in declaration order whatever order they were given in
+        assert_eq!(components.to_string(), "POLLER,SERVER,OUTBOX_RELAY");
+    }
+
+    #[test]
+    fn rejects_unknown_and_empty_component_lists() {
+        assert!(Components::parse("POLLER,WORKER").is_err());
+        assert!(Components::parse("").is_err());
+        assert!(Components::parse(" , ").is_err());
+    }
+
+    #[test]
+    fn modules_select_their_components() {
+        assert_eq!(Components::for_module("READER"), Components::parse("POLLER,RECONCILER,ARCHIVER").ok());
+        assert_eq!(Components::for_module("SERVER"), Components::parse("SERVER,OUTBOX_RELAY").ok());
+        assert_eq!(Components::for_module("MIGRATE"), Some(Components::default()));
+        assert_eq!(Components::for_module("reader"), None);
+        assert!(Components::module_names().iter().all(|name| Components::for_module(name).is_some()));
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use opentelemetry::{global, trace::TracerProvider as _};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+pub struct TelemetryOption {
+    // OTLP/HTTP collector, e.g. http://localhost:4318, spans are only logged when unset
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+}
+
+// Exports spans until shut down, shutting down flushes what is still batched
+pub struct Telemetry {
+    provider: Option<SdkTracerProvider>,
+}
+
+impl Telemetry {
+    // Installs the log output and, with an endpoint, the span export in W3C trace context
+    pub fn init(option: TelemetryOption) -> Self {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| "memo_events_mgt=info".into());
+        let TelemetryOption { otlp_endpoint, service_name } = option;
+        let provider = otlp_endpoint.and_then(|endpoint| {
+            let exporter = SpanExporter::builder()
+                .with_http()
+                .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+                .build();
+            match exporter {
+                Ok(exporter) => Some(SdkTracerProvider::builder()
+                    .with_batch_exporter(exporter)
+                    .with_resource(Resource::builder().with_service_name(service_name.clone()).build())
+                    .build()),
+                Err(e) => {
+                    eprintln!("Failed to build the OTLP exporter for {}, spans are not exported: {:?}", endpoint, e);
+                    None
+                }
+            }
+        });
+        let otel_layer = provider.as_ref()
+            .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer("memo-events-mgt")));
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(tracing_subscriber::fmt::layer())
+            .with(otel_layer)
+            .init();
+
+        Telemetry { provider }
+    }
+
+    pub fn shutdown(self) {
+        if let Some(provider) = self.provider {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Failed to flush spans: {:?}", e);
+            }
+        }
+    }
+}
+
+// Continues the trace whose `traceparent` (and `tracestate`) the carrier holds, a carrier
+// without one leaves the span as a new root
+pub fn set_remote_parent(span: &Span, carrier: &HashMap<String, String>) {
+    if !carrier.contains_key("traceparent") {
+        return;
+    }
+    let context = global::get_text_map_propagator(|propagator| propagator.extract(carrier));
+    if let Err(e) = span.set_parent(context) {
+        tracing::debug!("trace context not applied: {:?}", e);
+    }
+}
+
+// The span's trace context as `traceparent`/`tracestate` entries, for a message this process sends
+pub fn trace_carrier(span: &Span) -> HashMap<String, String> {
+    let mut carrier = HashMap::new();
+    global::get_text_map_propagator(|propagator| propagator.inject_context(&span.context(), &mut carrier));
+    carrier
+}
@@ -1,36 +1,49 @@
 use std::collections::HashMap;
 
 use aws_sdk_dynamodb::types::AttributeValue;
-use serde::Serialize;
-use serde_json::{to_string, Value};
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::{Map, Number, Value};
 
 
 use crate::errors::main::SerializationError;
 
-pub fn struct_to_hashmap<T: Serialize>(t: &T) -> Result<HashMap<String, AttributeValue>, SerializationError> {
-    // Convert the struct to a JSON string
-    let json = to_string(t).map_err(|e| SerializationError::new(&e.to_string()))?;
-    let map: HashMap<String, Value> = serde_json::from_str(&json).map_err(|e| SerializationError::new(&e.to_string()))?;
+// Encodes a struct as a DynamoDB item. Lists, maps, sets (see `serde_dynamo::string_set`/`number_set`),
+// binary (`serde_bytes`) and numbers are kept as their native attribute types.
+// `serde_json::Value` fields go through `json_to_attribute` instead, serde_dynamo cannot keep their numbers exact.
+pub fn to_attribute_map<T: Serialize>(t: &T) -> Result<HashMap<String, AttributeValue>, SerializationError> {
+    serde_dynamo::to_item(t).map_err(|e| SerializationError::new(&e.to_string()))
+}
 
-    let hashmap = map.into_iter().map(|(k, v)| {
-        let attr_value = match v {
-            Value::String(s) => AttributeValue::S(s),
-            Value::Number(n) => {
-                if let Some(i) = n.as_i64() {
-                    AttributeValue::N(i.to_string())
-                } else if let Some(f) = n.as_f64() {
-                    AttributeValue::N(f.to_string())
-                } else {
-                    AttributeValue::Null(true)
-                }
-            },
-            Value::Bool(b) => AttributeValue::Bool(b),
-            Value::Null => AttributeValue::Null(true),
-            // Add other conversions as needed (e.g., lists, maps)
-            _ => AttributeValue::Null(true), // Fallback for unsupported types
-        };
-        (k, attr_value)
-    }).collect();
+// Decodes a DynamoDB item, the inverse of `to_attribute_map`
+pub fn from_attribute_map<T: DeserializeOwned>(item: HashMap<String, AttributeValue>) -> Result<T, SerializationError> {
+    serde_dynamo::from_item(item).map_err(|e| SerializationError::new(&e.to_string()))
+}
 
-    Ok(hashmap)
-}
\ No newline at end of file
+// JSON value as a native attribute, numbers keep their exact decimal text (serde_json `arbitrary_precision`)
+pub fn json_to_attribute(value: &Value) -> AttributeValue {
+    match value {
+        Value::Null => AttributeValue::Null(true),
+        Value::Bool(value) => AttributeValue::Bool(*value),
+        Value::Number(value) => AttributeValue::N(value.to_string()),
+        Value::String(value) => AttributeValue::S(value.clone()),
+        Value::Array(values) => AttributeValue::L(values.iter().map(json_to_attribute).collect()),
+        Value::Object(values) => AttributeValue::M(values.iter().map(|(key, value)| (key.clone(), json_to_attribute(value))).collect()),
+    }
+}
+
+// The inverse of `json_to_attribute`, sets and binary have no JSON counterpart
+pub fn attribute_to_json(attribute: &AttributeValue) -> Result<Value, SerializationError> {
+    match attribute {
+        AttributeValue::Null(_) => Ok(Value::Null),
+        AttributeValue::Bool(value) => Ok(Value::Bool(*value)),
+        AttributeValue::N(value) => value.parse::<Number>()
+            .map(Value::Number)
+            .map_err(|e| SerializationError::new(&format!("invalid number {:?}: {}", value, e))),
+        AttributeValue::S(value) => Ok(Value::String(value.clone())),
+        AttributeValue::L(values) => Ok(Value::Array(values.iter().map(attribute_to_json).collect::<Result<_, _>>()?)),
+        AttributeValue::M(values) => Ok(Value::Object(values.iter()
+            .map(|(key, value)| Ok((key.clone(), attribute_to_json(value)?)))
+            .collect::<Result<Map<_, _>, SerializationError>>()?)),
+        other => Err(SerializationError::new(&format!("attribute {:?} has no JSON form", other))),
+    }
+}